@@ -0,0 +1,194 @@
+use crate::math_utils::{Vec3, Point3f};
+use crate::cube::{Cube, Scene};
+use crate::quad::Quad;
+
+const PIXEL: f32 = 1.0 / 16.0;
+
+// Plants drawn as crossed sprites, besides saplings and tulips
+const CROSS_PLANTS: [&str; 14] = [
+    "short_grass", "grass", "tall_grass", "fern", "large_fern", "dead_bush", "dandelion",
+    "poppy", "blue_orchid", "allium", "azure_bluet", "oxeye_daisy", "cornflower", "lily_of_the_valley",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Facing {
+    North, // -Z
+    East,  // +X
+    South, // +Z
+    West,  // -X
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Connections {
+    pub north: bool,
+    pub east: bool,
+    pub south: bool,
+    pub west: bool,
+}
+
+// Minecraft block shapes; all are built within a unit block at `position`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockModel {
+    Full,
+    Slab { top: bool },
+    Stairs { facing: Facing, upside_down: bool },
+    Fence(Connections),
+    Pane(Connections),
+    Cross,
+}
+
+impl BlockModel {
+    // Shape of a block state like "minecraft:oak_stairs[facing=east,half=top]"; blocks without
+    // a shape of their own are full cubes
+    pub fn from_block_state(block_state: &str) -> Self {
+        let (id, properties) = match block_state.split_once('[') {
+            Some((id, properties)) => (id, properties.trim_end_matches(']')),
+            None => (block_state, ""),
+        };
+        let name = id.rsplit(':').next().unwrap_or(id);
+        let property = |key: &str| {
+            properties.split(',')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim() == key)
+                .map(|(_, value)| value.trim())
+        };
+        let connections = || Connections {
+            north: property("north") == Some("true"),
+            east: property("east") == Some("true"),
+            south: property("south") == Some("true"),
+            west: property("west") == Some("true"),
+        };
+
+        if name.ends_with("_slab") {
+            match property("type") {
+                Some("double") => BlockModel::Full,
+                top => BlockModel::Slab { top: top == Some("top") },
+            }
+        } else if name.ends_with("_stairs") {
+            let facing = match property("facing") {
+                Some("east") => Facing::East,
+                Some("south") => Facing::South,
+                Some("west") => Facing::West,
+                _ => Facing::North,
+            };
+            BlockModel::Stairs { facing, upside_down: property("half") == Some("top") }
+        } else if name.ends_with("_fence") {
+            BlockModel::Fence(connections())
+        } else if name.ends_with("_pane") || name == "iron_bars" {
+            BlockModel::Pane(connections())
+        } else if CROSS_PLANTS.contains(&name) || name.ends_with("_sapling") || name.ends_with("_tulip") {
+            BlockModel::Cross
+        } else {
+            BlockModel::Full
+        }
+    }
+
+    pub fn add_to_scene(&self, scene: &mut Scene, position: Point3f, material_index: usize) {
+        match self {
+            BlockModel::Cross => {
+                for quad in cross_quads(position, material_index) {
+                    scene.add_quad(quad);
+                }
+            }
+            _ => {
+                for cube in self.boxes(position, material_index) {
+                    scene.add_cube(cube);
+                }
+            }
+        }
+    }
+
+    pub fn boxes(&self, position: Point3f, material_index: usize) -> Vec<Cube> {
+        // Boxes are described in pixels (0..16) inside the block
+        let pixel_box = |min: [f32; 3], max: [f32; 3]| {
+            Cube::new(
+                position + Vec3::new(min[0], min[1], min[2]) * PIXEL,
+                position + Vec3::new(max[0], max[1], max[2]) * PIXEL,
+                material_index,
+            ).in_block(position)
+        };
+
+        match *self {
+            BlockModel::Full => vec![pixel_box([0.0, 0.0, 0.0], [16.0, 16.0, 16.0])],
+            BlockModel::Slab { top } => {
+                let (bottom, height) = if top { (8.0, 16.0) } else { (0.0, 8.0) };
+                vec![pixel_box([0.0, bottom, 0.0], [16.0, height, 16.0])]
+            }
+            BlockModel::Stairs { facing, upside_down } => {
+                let (slab_min, slab_max, step_min, step_max) = if upside_down {
+                    (8.0, 16.0, 0.0, 8.0)
+                } else {
+                    (0.0, 8.0, 8.0, 16.0)
+                };
+                // The step sits on the half of the block the stairs face
+                let (x_range, z_range) = match facing {
+                    Facing::North => ((0.0, 16.0), (0.0, 8.0)),
+                    Facing::South => ((0.0, 16.0), (8.0, 16.0)),
+                    Facing::East => ((8.0, 16.0), (0.0, 16.0)),
+                    Facing::West => ((0.0, 8.0), (0.0, 16.0)),
+                };
+                vec![
+                    pixel_box([0.0, slab_min, 0.0], [16.0, slab_max, 16.0]),
+                    pixel_box([x_range.0, step_min, z_range.0], [x_range.1, step_max, z_range.1]),
+                ]
+            }
+            BlockModel::Fence(connections) => {
+                let mut boxes = vec![pixel_box([6.0, 0.0, 6.0], [10.0, 16.0, 10.0])];
+                // Two rails towards every connected neighbour
+                for (bottom, top) in [(6.0, 9.0), (12.0, 15.0)] {
+                    boxes.extend(arms(connections, 7.0, 9.0, 6.0, 10.0).into_iter()
+                        .map(|(min, max)| pixel_box([min[0], bottom, min[1]], [max[0], top, max[1]])));
+                }
+                boxes
+            }
+            BlockModel::Pane(connections) => {
+                let mut boxes = vec![pixel_box([7.0, 0.0, 7.0], [9.0, 16.0, 9.0])];
+                boxes.extend(arms(connections, 7.0, 9.0, 7.0, 9.0).into_iter()
+                    .map(|(min, max)| pixel_box([min[0], 0.0, min[1]], [max[0], 16.0, max[1]])));
+                boxes
+            }
+            BlockModel::Cross => Vec::new(),
+        }
+    }
+}
+
+// Horizontal (x, z) extents of the arms leaving a center post towards each connected side
+fn arms(connections: Connections, thin_min: f32, thin_max: f32, post_min: f32, post_max: f32) -> Vec<([f32; 2], [f32; 2])> {
+    let mut arms = Vec::new();
+    if connections.north {
+        arms.push(([thin_min, 0.0], [thin_max, post_min]));
+    }
+    if connections.south {
+        arms.push(([thin_min, post_max], [thin_max, 16.0]));
+    }
+    if connections.west {
+        arms.push(([0.0, thin_min], [post_min, thin_max]));
+    }
+    if connections.east {
+        arms.push(([post_max, thin_min], [16.0, thin_max]));
+    }
+    arms
+}
+
+// Two quads crossing diagonally through the block, as used by flowers, saplings and tall grass
+pub fn cross_quads(position: Point3f, material_index: usize) -> [Quad; 2] {
+    // Minecraft insets the diagonals slightly so the sprite does not touch the block edges
+    let inset = 0.8 * PIXEL;
+    let span = 1.0 - 2.0 * inset;
+    let up = Vec3::new(0.0, 1.0, 0.0);
+
+    [
+        Quad::new(
+            position + Vec3::new(inset, 0.0, inset),
+            Vec3::new(span, 0.0, span),
+            up,
+            material_index,
+        ),
+        Quad::new(
+            position + Vec3::new(inset, 0.0, 1.0 - inset),
+            Vec3::new(span, 0.0, -span),
+            up,
+            material_index,
+        ),
+    ]
+}
@@ -0,0 +1,506 @@
+use crate::math_utils::{Vec3, Color, Ray, EPSILON, reflect, refract, fresnel};
+use crate::cube::HitRecord;
+use crate::materials::ScatterResult;
+use std::f32::consts::PI;
+
+// Below this GGX alpha surfaces are treated as perfectly smooth
+const SMOOTH_ALPHA: f32 = 1e-3;
+
+// Orthonormal shading frame; BSDFs work in its local space where the normal is +Z
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+
+impl Frame {
+    // The tangent hint is projected into the surface, any direction works when it is degenerate
+    pub fn new(normal: Vec3, tangent_hint: Vec3) -> Self {
+        let tangent = (tangent_hint - normal * normal.dot(&tangent_hint))
+            .try_normalize(EPSILON)
+            .unwrap_or_else(|| {
+                let helper = if normal.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+                helper.cross(&normal).normalize()
+            });
+        let bitangent = normal.cross(&tangent);
+        Self { tangent, bitangent, normal }
+    }
+
+    pub fn to_local(self, v: &Vec3) -> Vec3 {
+        Vec3::new(v.dot(&self.tangent), v.dot(&self.bitangent), v.dot(&self.normal))
+    }
+
+    pub fn to_world(self, v: &Vec3) -> Vec3 {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub wi: Vec3,
+    pub weight: Color, // f * |cos| / pdf
+    pub pdf: f32,      // Solid angle density, 0 for delta lobes
+    pub is_specular: bool,
+}
+
+// Directions are local to the shading frame and both point away from the surface.
+// `wo` is towards the viewer and always in the upper hemisphere.
+pub trait Bsdf {
+    // `uc` picks a lobe, `u` the direction within it
+    fn sample(&self, wo: &Vec3, uc: f32, u: (f32, f32)) -> Option<BsdfSample>;
+    // BSDF value times |cos| of `wi`; delta lobes evaluate to zero
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color;
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f32;
+}
+
+fn schlick(f0: Color, cos_theta: f32) -> Color {
+    let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+    f0 + (Color::repeat(1.0) - f0) * weight
+}
+
+fn cosine_hemisphere(u: (f32, f32)) -> Vec3 {
+    let r = u.0.sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u.0).max(0.0).sqrt())
+}
+
+fn mirror(wo: &Vec3) -> Vec3 {
+    Vec3::new(-wo.x, -wo.y, wo.z)
+}
+
+// Isotropic GGX (Trowbridge-Reitz) microfacet distribution
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    pub alpha: f32,
+}
+
+impl Ggx {
+    // Roughness is perceptual; squaring it gives a perceptually linear highlight size
+    pub fn from_roughness(roughness: f32) -> Self {
+        Self { alpha: roughness.clamp(0.0, 1.0).powi(2) }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < SMOOTH_ALPHA
+    }
+
+    pub fn d(&self, h: &Vec3) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let denominator = h.z * h.z * (a2 - 1.0) + 1.0;
+        a2 / (PI * denominator * denominator)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f32 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) * 0.5
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height-correlated masking and shadowing
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of sampling `h` as a visible normal from `wo`
+    pub fn visible_pdf(&self, wo: &Vec3, h: &Vec3) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z
+    }
+
+    // Samples a normal visible from `wo` (Heitz 2018)
+    pub fn sample_visible(&self, wo: &Vec3, u: (f32, f32)) -> Vec3 {
+        let stretched = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).normalize();
+        let length2 = stretched.x * stretched.x + stretched.y * stretched.y;
+        let t1 = if length2 > 0.0 {
+            Vec3::new(-stretched.y, stretched.x, 0.0) / length2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = stretched.cross(&t1);
+
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + stretched.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        let h = t1 * p1 + t2 * p2 + stretched * p3;
+        Vec3::new(self.alpha * h.x, self.alpha * h.y, h.z.max(1e-6)).normalize()
+    }
+}
+
+// Ideal diffuse reflection
+pub struct Lambertian {
+    pub albedo: Color,
+}
+
+impl Bsdf for Lambertian {
+    fn sample(&self, _wo: &Vec3, _uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        let wi = cosine_hemisphere(u);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi, weight: self.albedo, pdf: wi.z / PI, is_specular: false })
+    }
+
+    fn eval(&self, _wo: &Vec3, wi: &Vec3) -> Color {
+        self.albedo * (wi.z.max(0.0) / PI)
+    }
+
+    fn pdf(&self, _wo: &Vec3, wi: &Vec3) -> f32 {
+        wi.z.max(0.0) / PI
+    }
+}
+
+// Diffuse transmission through thin surfaces such as leaves, lit from behind
+pub struct Translucent {
+    pub transmittance: Color,
+}
+
+impl Bsdf for Translucent {
+    fn sample(&self, _wo: &Vec3, _uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        let wi = -cosine_hemisphere(u);
+        if wi.z >= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi, weight: self.transmittance, pdf: -wi.z / PI, is_specular: false })
+    }
+
+    fn eval(&self, _wo: &Vec3, wi: &Vec3) -> Color {
+        self.transmittance * ((-wi.z).max(0.0) / PI)
+    }
+
+    fn pdf(&self, _wo: &Vec3, wi: &Vec3) -> f32 {
+        (-wi.z).max(0.0) / PI
+    }
+}
+
+// Metal with GGX microfacets and Schlick's Fresnel from its color at normal incidence
+pub struct Conductor {
+    pub f0: Color,
+    pub distribution: Ggx,
+}
+
+impl Bsdf for Conductor {
+    fn sample(&self, wo: &Vec3, _uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        if self.distribution.is_smooth() {
+            let wi = mirror(wo);
+            return Some(BsdfSample { wi, weight: schlick(self.f0, wo.z), pdf: 0.0, is_specular: true });
+        }
+
+        let h = self.distribution.sample_visible(wo, u);
+        let wi = reflect(-wo, h);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        // D and the visible normal pdf cancel, leaving F G / G1
+        let weight = schlick(self.f0, wo.dot(&h)) * (self.distribution.g(wo, &wi) / self.distribution.g1(wo));
+        Some(BsdfSample { wi, weight, pdf: self.pdf(wo, &wi), is_specular: false })
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        if self.distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::zeros();
+        }
+        let h = (wo + wi).normalize();
+        let d = self.distribution.d(&h);
+        let g = self.distribution.g(wo, wi);
+        schlick(self.f0, wo.dot(&h)) * (d * g / (4.0 * wo.z))
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        if self.distribution.is_smooth() || wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).normalize();
+        self.distribution.visible_pdf(wo, &h) / (4.0 * wo.dot(&h))
+    }
+}
+
+// Glass and water: GGX microfacets that reflect or refract by the exact Fresnel term.
+// `eta` is the ratio of refractive indices, viewer side over far side, as in `refract`.
+pub struct Dielectric {
+    pub eta: f32,
+    pub distribution: Ggx,
+    pub transmittance: Color,
+}
+
+impl Dielectric {
+    // Half vector of a refraction pair, oriented into the upper hemisphere
+    fn transmission_half_vector(&self, wo: &Vec3, wi: &Vec3) -> Option<Vec3> {
+        let h = (wo * self.eta + wi).try_normalize(EPSILON)?;
+        Some(if h.z < 0.0 { -h } else { h })
+    }
+
+    // Jacobian of the half vector with respect to the refracted direction
+    fn transmission_jacobian(&self, wo: &Vec3, wi: &Vec3, h: &Vec3) -> f32 {
+        let denominator = wi.dot(h) + wo.dot(h) * self.eta;
+        wi.dot(h).abs() / (denominator * denominator)
+    }
+}
+
+impl Bsdf for Dielectric {
+    fn sample(&self, wo: &Vec3, uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        if self.distribution.is_smooth() {
+            let normal = Vec3::new(0.0, 0.0, 1.0);
+            let reflectance = fresnel(wo.z, self.eta);
+            if uc < reflectance {
+                return Some(BsdfSample { wi: mirror(wo), weight: Color::repeat(1.0), pdf: 0.0, is_specular: true });
+            }
+            let wi = refract(-wo, normal, self.eta)?;
+            return Some(BsdfSample { wi, weight: self.transmittance, pdf: 0.0, is_specular: true });
+        }
+
+        let h = self.distribution.sample_visible(wo, u);
+        let reflectance = fresnel(wo.dot(&h), self.eta);
+        let wi = if uc < reflectance {
+            let wi = reflect(-wo, h);
+            if wi.z <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let wi = refract(-wo, h, self.eta)?;
+            if wi.z >= 0.0 {
+                return None;
+            }
+            wi
+        };
+
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi, weight: self.eval(wo, &wi) / pdf, pdf, is_specular: false })
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        if self.distribution.is_smooth() || wo.z <= 0.0 || wi.z == 0.0 {
+            return Color::zeros();
+        }
+
+        if wi.z > 0.0 {
+            let h = (wo + wi).normalize();
+            let reflectance = fresnel(wo.dot(&h), self.eta);
+            let d = self.distribution.d(&h);
+            let g = self.distribution.g(wo, wi);
+            return Color::repeat(reflectance * d * g / (4.0 * wo.z));
+        }
+
+        let Some(h) = self.transmission_half_vector(wo, wi) else {
+            return Color::zeros();
+        };
+        // Microfacets seen from behind cannot refract between the two directions
+        if wo.dot(&h) <= 0.0 || wi.dot(&h) >= 0.0 {
+            return Color::zeros();
+        }
+        let transmitted = 1.0 - fresnel(wo.dot(&h), self.eta);
+        let d = self.distribution.d(&h);
+        let g = self.distribution.g(wo, wi);
+        let value = transmitted * d * g * wo.dot(&h) * self.transmission_jacobian(wo, wi, &h) / wo.z;
+        self.transmittance * value
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        if self.distribution.is_smooth() || wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        if wi.z > 0.0 {
+            let h = (wo + wi).normalize();
+            let reflectance = fresnel(wo.dot(&h), self.eta);
+            return reflectance * self.distribution.visible_pdf(wo, &h) / (4.0 * wo.dot(&h));
+        }
+
+        let Some(h) = self.transmission_half_vector(wo, wi) else {
+            return 0.0;
+        };
+        if wo.dot(&h) <= 0.0 || wi.dot(&h) >= 0.0 {
+            return 0.0;
+        }
+        let transmitted = 1.0 - fresnel(wo.dot(&h), self.eta);
+        transmitted * self.distribution.visible_pdf(wo, &h) * self.transmission_jacobian(wo, wi, &h)
+    }
+}
+
+// Diffuse base under a clear coat. Light the coat reflects on the way in or out
+// never reaches the base, which keeps the sum of both lobes below one.
+pub struct Plastic {
+    pub diffuse: Color,
+    pub f0: f32, // Coat reflectance at normal incidence
+    pub distribution: Ggx,
+}
+
+impl Plastic {
+    fn coat(&self, cos_theta: f32) -> f32 {
+        schlick(Color::repeat(self.f0), cos_theta).x
+    }
+
+    // Probability of sampling the coat; never zero so highlights are always found
+    fn coat_probability(&self, wo: &Vec3) -> f32 {
+        let coat = self.coat(wo.z);
+        let diffuse = (1.0 - coat) * self.diffuse.max();
+        (coat / (coat + diffuse).max(EPSILON)).clamp(0.1, 0.9)
+    }
+
+    fn coat_lobe(&self) -> Conductor {
+        Conductor { f0: Color::repeat(self.f0), distribution: self.distribution }
+    }
+
+    fn diffuse_eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        if wi.z <= 0.0 {
+            return Color::zeros();
+        }
+        self.diffuse * ((1.0 - self.coat(wo.z)) * (1.0 - self.coat(wi.z)) * wi.z / PI)
+    }
+}
+
+impl Bsdf for Plastic {
+    fn sample(&self, wo: &Vec3, uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        let coat_probability = self.coat_probability(wo);
+
+        if uc < coat_probability {
+            let coat = self.coat_lobe().sample(wo, uc / coat_probability, u)?;
+            if coat.is_specular {
+                return Some(BsdfSample { weight: coat.weight / coat_probability, ..coat });
+            }
+            let pdf = self.pdf(wo, &coat.wi);
+            return Some(BsdfSample { weight: self.eval(wo, &coat.wi) / pdf, pdf, ..coat });
+        }
+
+        let wi = cosine_hemisphere(u);
+        let pdf = self.pdf(wo, &wi);
+        if wi.z <= 0.0 || pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi, weight: self.eval(wo, &wi) / pdf, pdf, is_specular: false })
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        self.coat_lobe().eval(wo, wi) + self.diffuse_eval(wo, wi)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        let coat_probability = self.coat_probability(wo);
+        coat_probability * self.coat_lobe().pdf(wo, wi) + (1.0 - coat_probability) * wi.z.max(0.0) / PI
+    }
+}
+
+// Stochastic blend of two BSDFs, `amount` being the share of the second
+pub struct Mix {
+    pub first: Box<dyn Bsdf>,
+    pub second: Box<dyn Bsdf>,
+    pub amount: f32,
+}
+
+impl Mix {
+    // Skips the blend when one side has no share at all
+    pub fn boxed(first: Box<dyn Bsdf>, second: Box<dyn Bsdf>, amount: f32) -> Box<dyn Bsdf> {
+        if amount <= 0.0 {
+            first
+        } else if amount >= 1.0 {
+            second
+        } else {
+            Box::new(Mix { first, second, amount })
+        }
+    }
+}
+
+impl Bsdf for Mix {
+    fn sample(&self, wo: &Vec3, uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        let amount = self.amount.clamp(0.0, 1.0);
+        let (sample, probability) = if uc < amount {
+            (self.second.sample(wo, uc / amount, u)?, amount)
+        } else {
+            (self.first.sample(wo, (uc - amount) / (1.0 - amount), u)?, 1.0 - amount)
+        };
+
+        // Delta lobes cannot be evaluated, their weight already accounts for the choice
+        if sample.is_specular {
+            return Some(sample);
+        }
+        let pdf = self.pdf(wo, &sample.wi);
+        if pdf <= 0.0 || probability <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { weight: self.eval(wo, &sample.wi) / pdf, pdf, ..sample })
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let amount = self.amount.clamp(0.0, 1.0);
+        self.first.eval(wo, wi) * (1.0 - amount) + self.second.eval(wo, wi) * amount
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        let amount = self.amount.clamp(0.0, 1.0);
+        self.first.pdf(wo, wi) * (1.0 - amount) + self.second.pdf(wo, wi) * amount
+    }
+}
+
+// A BSDF placed on a surface, taking world space directions
+pub struct SurfaceBsdf {
+    pub frame: Frame,
+    pub geometric_normal: Vec3, // Faces the viewer like the shading normal
+    pub bsdf: Box<dyn Bsdf>,
+}
+
+impl SurfaceBsdf {
+    // Interpolated and mapped normals can disagree with the real surface; such samples are dropped
+    fn same_side(&self, wi: &Vec3, local_wi: &Vec3) -> bool {
+        (wi.dot(&self.geometric_normal) > 0.0) == (local_wi.z > 0.0)
+    }
+
+    pub fn sample(&self, wo: &Vec3, uc: f32, u: (f32, f32)) -> Option<BsdfSample> {
+        let local_wo = self.frame.to_local(wo);
+        if local_wo.z <= 0.0 {
+            return None;
+        }
+        let sample = self.bsdf.sample(&local_wo, uc, u)?;
+        let wi = self.frame.to_world(&sample.wi);
+        if !self.same_side(&wi, &sample.wi) {
+            return None;
+        }
+        Some(BsdfSample { wi, ..sample })
+    }
+
+    // Continues a path hitting the surface along `ray`; `uc` and `u` are as in `sample`
+    pub fn scatter(&self, ray: &Ray, hit: &HitRecord, uc: f32, u: (f32, f32)) -> Option<ScatterResult> {
+        let sample = self.sample(&-ray.direction, uc, u)?;
+        Some(ScatterResult {
+            scattered_ray: Ray::new(hit.point.into(), sample.wi),
+            attenuation: sample.weight,
+            pdf: sample.pdf,
+            is_specular: sample.is_specular,
+        })
+    }
+
+    pub fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let (local_wo, local_wi) = (self.frame.to_local(wo), self.frame.to_local(wi));
+        if local_wo.z <= 0.0 || !self.same_side(wi, &local_wi) {
+            return Color::zeros();
+        }
+        self.bsdf.eval(&local_wo, &local_wi)
+    }
+
+    pub fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        let (local_wo, local_wi) = (self.frame.to_local(wo), self.frame.to_local(wi));
+        if local_wo.z <= 0.0 || !self.same_side(wi, &local_wi) {
+            return 0.0;
+        }
+        self.bsdf.pdf(&local_wo, &local_wi)
+    }
+}
@@ -9,6 +9,8 @@ pub struct Camera {
     pub aspect_ratio: f32,
     pub near: f32,
     pub far: f32,
+    pub aperture: f32, // Lens radius; zero is a pinhole with everything in focus
+    pub orthographic: Option<f32>, // Half the height of the view for parallel projection, in world units
     
     // Orbital controls
     pub distance: f32,
@@ -28,6 +30,8 @@ impl Camera {
             aspect_ratio,
             near: 0.1,
             far: 1000.0,
+            aperture: 0.0,
+            orthographic: None,
             distance,
             theta: 0.0,
             phi: std::f32::consts::PI * 0.25, // 45 degrees
@@ -38,6 +42,21 @@ impl Camera {
         camera
     }
     
+    // `up` only needs to lie in the plane of the view's vertical, so any roll can be kept
+    pub fn look_at(position: Point3f, target: Point3f, up: Vec3, fov: f32, aspect_ratio: f32) -> Self {
+        let offset = position - target;
+        let distance = offset.magnitude().max(1e-3);
+
+        let mut camera = Self::new(target, distance, fov, aspect_ratio);
+        camera.up = up;
+        camera.theta = offset.z.atan2(offset.x);
+        camera.phi = (offset.y / distance).clamp(-1.0, 1.0).acos();
+        camera.min_distance = camera.min_distance.min(distance);
+        camera.max_distance = camera.max_distance.max(distance);
+        camera.update_position();
+        camera
+    }
+
     pub fn update_position(&mut self) {
         // Convert spherical coordinates to cartesian
         let x = self.distance * self.phi.sin() * self.theta.cos();
@@ -64,15 +83,22 @@ impl Camera {
     }
     
     pub fn get_ray(&self, u: f32, v: f32) -> Ray {
+        let w = (self.position - self.target).normalize();
+        let u_vec = self.up.cross(&w).normalize();
+        let v_vec = w.cross(&u_vec);
+        
+        // Parallel rays leave a window the size of the view
+        if let Some(half_height) = self.orthographic {
+            let half_width = self.aspect_ratio * half_height;
+            let origin = self.position + u_vec * ((2.0 * u - 1.0) * half_width) + v_vec * ((2.0 * v - 1.0) * half_height);
+            return Ray::new(origin, -w);
+        }
+        
         // Convert screen coordinates to world ray
         let theta = self.fov * std::f32::consts::PI / 180.0;
         let half_height = (theta / 2.0).tan();
         let half_width = self.aspect_ratio * half_height;
         
-        let w = (self.position - self.target).normalize();
-        let u_vec = self.up.cross(&w).normalize();
-        let v_vec = w.cross(&u_vec);
-        
         let lower_left_corner = self.position.coords 
             - half_width * u_vec 
             - half_height * v_vec 
@@ -86,6 +112,34 @@ impl Camera {
         Ray::new(self.position, direction.normalize())
     }
     
+    // Ray through a thin lens, focused on the target; `lens` picks the point on the lens
+    pub fn get_lens_ray(&self, u: f32, v: f32, lens: (f32, f32)) -> Ray {
+        let ray = self.get_ray(u, v);
+        if self.aperture <= 0.0 {
+            return ray;
+        }
+        
+        // Concentric mapping of the square onto the lens disc
+        let (a, b) = (2.0 * lens.0 - 1.0, 2.0 * lens.1 - 1.0);
+        let (radius, angle) = if a == 0.0 && b == 0.0 {
+            (0.0, 0.0)
+        } else if a.abs() > b.abs() {
+            (a, std::f32::consts::FRAC_PI_4 * (b / a))
+        } else {
+            (b, std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (a / b))
+        };
+        
+        let w = (self.position - self.target).normalize();
+        let u_vec = self.up.cross(&w).normalize();
+        let v_vec = w.cross(&u_vec);
+        let offset = (u_vec * angle.cos() + v_vec * angle.sin()) * (radius * self.aperture);
+        
+        // Points on the focal plane through the target stay sharp
+        let focus = ray.at(self.distance / ray.direction.dot(&-w));
+        let origin = self.position + offset;
+        Ray::new(origin, focus - origin)
+    }
+    
     pub fn get_view_matrix(&self) -> nalgebra::Matrix4<f32> {
         nalgebra::Matrix4::look_at_rh(
             &self.position,
@@ -1,10 +1,15 @@
 use crate::math_utils::{Vec3, Point3f, Ray, EPSILON};
 use crate::materials::Material;
+use crate::mesh::Mesh;
+use crate::instance::Instance;
+use crate::quad::Quad;
 
 #[derive(Debug, Clone)]
 pub struct HitRecord {
     pub point: Vec3,
     pub normal: Vec3,
+    pub tangent: Vec3,   // Surface direction of increasing u
+    pub bitangent: Vec3, // Surface direction of increasing v
     pub t: f32,
     pub u: f32,
     pub v: f32,
@@ -24,17 +29,37 @@ pub struct Cube {
     pub min: Point3f,
     pub max: Point3f,
     pub material_index: usize,
+    pub face_materials: Option<[usize; 6]>, // +X, -X, +Y, -Y, +Z, -Z
+    pub block_origin: Option<Point3f>, // Sub-boxes map UVs relative to their enclosing block
 }
 
 impl Cube {
     pub fn new(min: Point3f, max: Point3f, material_index: usize) -> Self {
-        Self { min, max, material_index }
+        Self { min, max, material_index, face_materials: None, block_origin: None }
+    }
+    
+    pub fn with_face_materials(min: Point3f, max: Point3f, face_materials: [usize; 6]) -> Self {
+        Self { min, max, material_index: face_materials[2], face_materials: Some(face_materials), block_origin: None }
+    }
+    
+    pub fn in_block(mut self, block_origin: Point3f) -> Self {
+        self.block_origin = Some(block_origin);
+        self
+    }
+    
+    fn material_for_face(&self, face: i32) -> usize {
+        match (self.face_materials, face) {
+            (Some(faces), 1..=3) => faces[(face as usize - 1) * 2],
+            (Some(faces), -3..=-1) => faces[(-face as usize - 1) * 2 + 1],
+            _ => self.material_index,
+        }
     }
     
     pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let mut t_near = t_min;
         let mut t_far = t_max;
         let mut hit_face = 0; // 0=x, 1=y, 2=z, with sign indicating direction
+        let mut exit_face = 0; // Face the ray leaves through, used when it starts inside
         
         // Check intersection with each pair of parallel planes
         for axis in 0..3 {
@@ -61,6 +86,7 @@ impl Cube {
                 
                 if t_max_axis < t_far {
                     t_far = t_max_axis;
+                    exit_face = if t1 < t2 { axis as i32 + 1 } else { -(axis as i32 + 1) };
                 }
                 
                 if t_near > t_far {
@@ -73,21 +99,23 @@ impl Cube {
             return None;
         }
         
-        let t = if t_near > t_min { t_near } else { t_far };
+        let (t, hit_face) = if t_near > t_min { (t_near, hit_face) } else { (t_far, exit_face) };
         if t < t_min || t > t_max {
             return None;
         }
         
         let hit_point = ray.at(t);
-        let (normal, u, v) = self.get_face_normal_and_uv(hit_point.coords, hit_face);
+        let (normal, tangent, bitangent, u, v) = self.get_face_normal_and_uv(hit_point.coords, hit_face);
         
         let mut hit_record = HitRecord {
             point: hit_point.coords,
             normal: Vec3::zeros(),
+            tangent,
+            bitangent,
             t,
             u,
             v,
-            material_index: self.material_index,
+            material_index: self.material_for_face(hit_face),
             front_face: false,
         };
         
@@ -95,42 +123,53 @@ impl Cube {
         Some(hit_record)
     }
     
-    fn get_face_normal_and_uv(&self, point: Vec3, face: i32) -> (Vec3, f32, f32) {
-        let size = self.max - self.min;
-        let relative = point - self.min.coords;
+    // Returns the outward normal, the tangent frame along u and v, and the texture coordinates
+    fn get_face_normal_and_uv(&self, point: Vec3, face: i32) -> (Vec3, Vec3, Vec3, f32, f32) {
+        // Sub-boxes show the part of the texture matching their position inside the block
+        let (origin, size) = match self.block_origin {
+            Some(block_origin) => (block_origin.coords, Vec3::new(1.0, 1.0, 1.0)),
+            None => (self.min.coords, self.max - self.min),
+        };
+        let relative = point - origin;
         
         match face.abs() {
             1 => { // X face
                 let normal = if face > 0 { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(-1.0, 0.0, 0.0) };
                 let u = relative.z / size.z;
-                let v = relative.y / size.y;
-                (normal, u, v)
+                let v = 1.0 - relative.y / size.y; // Image rows go top to bottom
+                (normal, Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, -1.0, 0.0), u, v)
             },
             2 => { // Y face  
                 let normal = if face > 0 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(0.0, -1.0, 0.0) };
                 let u = relative.x / size.x;
                 let v = relative.z / size.z;
-                (normal, u, v)
+                (normal, Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), u, v)
             },
             3 => { // Z face
                 let normal = if face > 0 { Vec3::new(0.0, 0.0, 1.0) } else { Vec3::new(0.0, 0.0, -1.0) };
                 let u = relative.x / size.x;
-                let v = relative.y / size.y;
-                (normal, u, v)
+                let v = 1.0 - relative.y / size.y;
+                (normal, Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, -1.0, 0.0), u, v)
             },
-            _ => (Vec3::new(0.0, 1.0, 0.0), 0.0, 0.0),
+            _ => (Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0, 0.0),
         }
     }
 }
 
 pub struct Scene {
     pub cubes: Vec<Cube>,
+    pub meshes: Vec<Mesh>,
+    pub instances: Vec<Instance>,
+    pub quads: Vec<Quad>,
 }
 
 impl Scene {
     pub fn new() -> Self {
         Self {
             cubes: Vec::new(),
+            meshes: Vec::new(),
+            instances: Vec::new(),
+            quads: Vec::new(),
         }
     }
     
@@ -138,6 +177,30 @@ impl Scene {
         self.cubes.push(cube);
     }
     
+    pub fn add_mesh(&mut self, mesh: Mesh) {
+        self.meshes.push(mesh);
+    }
+    
+    pub fn add_quad(&mut self, quad: Quad) {
+        self.quads.push(quad);
+    }
+    
+    pub fn add_instance(&mut self, instance: Instance) {
+        self.instances.push(instance);
+    }
+    
+    pub fn bounds(&self) -> Option<(Point3f, Point3f)> {
+        let boxes = self.cubes.iter().map(|cube| (cube.min, cube.max))
+            .chain(self.meshes.iter().map(|mesh| (mesh.bounds_min, mesh.bounds_max)))
+            .chain(self.quads.iter().map(|quad| quad.bounds()))
+            .chain(self.instances.iter().filter_map(|instance| instance.bounds()));
+        
+        boxes.fold(None, |bounds, (min, max)| match bounds {
+            Some((bounds_min, bounds_max)) => Some((min.inf(&bounds_min), max.sup(&bounds_max))),
+            None => Some((min, max)),
+        })
+    }
+    
     pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let mut closest_hit: Option<HitRecord> = None;
         let mut closest_t = t_max;
@@ -149,6 +212,27 @@ impl Scene {
             }
         }
         
+        for mesh in &self.meshes {
+            if let Some(hit) = mesh.hit(ray, t_min, closest_t) {
+                closest_t = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+        
+        for quad in &self.quads {
+            if let Some(hit) = quad.hit(ray, t_min, closest_t) {
+                closest_t = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+        
+        for instance in &self.instances {
+            if let Some(hit) = instance.hit(ray, t_min, closest_t) {
+                closest_t = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+        
         closest_hit
     }
 }
@@ -0,0 +1,237 @@
+use crate::math_utils::Color;
+use std::f32::consts::PI;
+
+// Shape a sample's contribution takes around the point it landed on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    Box,      // Every sample inside the radius counts the same; with radius 0.5, a plain average
+    Tent,     // Linear falloff, slightly softer than box
+    Gaussian, // Smooth falloff, soft but free of ringing
+    Mitchell, // Mitchell-Netravali cubic with B = C = 1/3, sharp with little ringing
+    Lanczos,  // Windowed sinc with two lobes, the sharpest but rings around hard edges
+}
+
+impl FilterKind {
+    pub fn next(&self) -> Self {
+        match self {
+            FilterKind::Box => FilterKind::Tent,
+            FilterKind::Tent => FilterKind::Gaussian,
+            FilterKind::Gaussian => FilterKind::Mitchell,
+            FilterKind::Mitchell => FilterKind::Lanczos,
+            FilterKind::Lanczos => FilterKind::Box,
+        }
+    }
+}
+
+// Pixel reconstruction filter; samples reach every pixel whose center lies within `radius`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f32, // In pixels
+}
+
+impl Filter {
+    // The filter with its usual radius
+    pub fn new(kind: FilterKind) -> Self {
+        let radius = match kind {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell | FilterKind::Lanczos => 2.0,
+        };
+        Self { kind, radius }
+    }
+
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = radius.max(0.5);
+        self
+    }
+
+    // Weight of a sample at offset (dx, dy) from a pixel center; separable in x and y
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, offset: f32) -> f32 {
+        let x = offset.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x / self.radius,
+            FilterKind::Gaussian => {
+                // Shifted down so the filter reaches zero at the radius instead of stopping short
+                let sigma = self.radius / 3.0;
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(self.radius)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / self.radius),
+            FilterKind::Lanczos => {
+                let x = 2.0 * x / self.radius;
+                sinc(x) * sinc(x / 2.0)
+            }
+        }
+    }
+}
+
+// Mitchell-Netravali cubic over [0, 2], with B = C = 1/3
+fn mitchell(x: f32) -> f32 {
+    const B: f32 = 1.0 / 3.0;
+    const C: f32 = 1.0 / 3.0;
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * B - 6.0 * C) * x.powi(3) + (-18.0 + 12.0 * B + 6.0 * C) * x * x + (6.0 - 2.0 * B)
+    } else {
+        (-B - 6.0 * C) * x.powi(3) + (6.0 * B + 30.0 * C) * x * x + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)
+    };
+    value / 6.0
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+// Splats of each pixel's samples onto the pixels around it, kept apart per source pixel so
+// each is divided by its own sample count. A pixel adaptive sampling gave 64 samples then
+// weighs no more on its neighbors than one that stopped at 8.
+pub struct Film {
+    width: u32,
+    height: u32,
+    top: u32, // First row of source pixels this film holds
+    rows: u32,
+    filter: Filter,
+    reach: u32, // Furthest a sample's splats land from its source pixel
+    weighted_sum: Vec<Color>, // Footprint of every source pixel, row by row
+    weight_sum: Vec<f32>,
+    samples: Vec<u32>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Self {
+        Self::band(width, height, 0, height, filter)
+    }
+
+    fn band(width: u32, height: u32, top: u32, rows: u32, filter: Filter) -> Self {
+        let reach = (filter.radius + 0.5).ceil() as u32 - 1;
+        let pixels = (width * rows) as usize;
+        let size = pixels * ((2 * reach + 1) * (2 * reach + 1)) as usize;
+        Self {
+            width, height, top, rows, filter, reach,
+            weighted_sum: vec![Color::zeros(); size],
+            weight_sum: vec![0.0; size],
+            samples: vec![0; pixels],
+        }
+    }
+
+    // Empty film holding the source pixels of row `y`, so rows render apart
+    pub fn row_tile(&self, y: u32) -> Film {
+        Self::band(self.width, self.height, y, 1, self.filter)
+    }
+
+    // Splats a sample of pixel `source` at `position`, in pixels from the top left corner
+    pub fn add_sample(&mut self, source: (u32, u32), position: (f32, f32), color: Color) {
+        let side = 2 * self.reach as i32 + 1;
+        let pixel = ((source.1 - self.top) * self.width + source.0) as usize;
+        self.samples[pixel] += 1;
+
+        // Jitter rounded up onto the far edge of the pixel is pulled back inside it
+        let inside = |position: f32, source: u32| position.clamp(source as f32, (source as f32 + 1.0).next_down());
+        let position = (inside(position.0, source.0), inside(position.1, source.1));
+
+        // Pixels whose center lies within the radius, half open so a box of radius 0.5 picks one,
+        // and never past the footprint kept for the source pixel
+        let radius = self.filter.radius;
+        let reach = self.reach as i32;
+        let span = |center: f32, source: u32, size: u32| {
+            let first = ((center - 0.5 - radius).floor() as i32 + 1).max(0).max(source as i32 - reach);
+            let last = ((center - 0.5 + radius).floor() as i32).min(size as i32 - 1).min(source as i32 + reach);
+            first..last + 1
+        };
+
+        for y in span(position.1, source.1, self.height) {
+            for x in span(position.0, source.0, self.width) {
+                let weight = self.filter.evaluate(x as f32 + 0.5 - position.0, y as f32 + 0.5 - position.1);
+                if weight != 0.0 {
+                    let offset = (y - source.1 as i32 + reach) * side + x - source.0 as i32 + reach;
+                    let index = pixel * (side * side) as usize + offset as usize;
+                    self.weighted_sum[index] += color * weight;
+                    self.weight_sum[index] += weight;
+                }
+            }
+        }
+    }
+
+    // Adds the samples of a film covering some of the same rows
+    pub fn merge(&mut self, other: &Film) {
+        let footprint = ((2 * self.reach + 1) * (2 * self.reach + 1)) as usize;
+        for row in other.top..other.top + other.rows {
+            if row < self.top || row >= self.top + self.rows {
+                continue;
+            }
+            let from = ((row - other.top) * self.width) as usize;
+            let to = ((row - self.top) * self.width) as usize;
+            for x in 0..self.width as usize {
+                self.samples[to + x] += other.samples[from + x];
+            }
+            for i in 0..self.width as usize * footprint {
+                self.weighted_sum[to * footprint + i] += other.weighted_sum[from * footprint + i];
+                self.weight_sum[to * footprint + i] += other.weight_sum[from * footprint + i];
+            }
+        }
+    }
+
+    // Filtered pixel colors. Filters with negative lobes can ring below zero, which is clamped.
+    pub fn pixels(&self) -> Vec<Color> {
+        let reach = self.reach as i32;
+        let side = 2 * reach + 1;
+        let mut weighted_sum = vec![Color::zeros(); (self.width * self.height) as usize];
+        let mut weight_sum = vec![0.0; weighted_sum.len()];
+        for row in 0..self.rows {
+            for column in 0..self.width {
+                let pixel = (row * self.width + column) as usize;
+                let samples = self.samples[pixel] as f32;
+                if samples == 0.0 {
+                    continue;
+                }
+                let source_y = (self.top + row) as i32;
+                for i in 0..(side * side) as usize {
+                    let x = column as i32 + i as i32 % side - reach;
+                    let y = source_y + i as i32 / side - reach;
+                    if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+                        continue;
+                    }
+                    let index = pixel * (side * side) as usize + i;
+                    let target = (y as u32 * self.width + x as u32) as usize;
+                    weighted_sum[target] += self.weighted_sum[index] / samples;
+                    weight_sum[target] += self.weight_sum[index] / samples;
+                }
+            }
+        }
+
+        weighted_sum.iter()
+            .zip(&weight_sum)
+            .map(|(sum, &weight)| {
+                if weight > 0.0 { (sum / weight).map(|c| c.max(0.0)) } else { Color::zeros() }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_rounded_onto_the_pixel_edge_stay_in_their_pixel() {
+        for kind in [FilterKind::Box, FilterKind::Mitchell] {
+            let mut film = Film::new(4, 3, Filter::new(kind));
+            let mut tile = film.row_tile(1);
+            tile.add_sample((3, 1), (4.0, 2.0), Color::new(1.0, 0.5, 0.25));
+            film.merge(&tile);
+            assert_eq!(film.pixels()[7], Color::new(1.0, 0.5, 0.25));
+        }
+    }
+}
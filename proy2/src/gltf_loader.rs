@@ -0,0 +1,278 @@
+use crate::math_utils::{Vec3, Point3f};
+use crate::materials::{Material, TextureManager, AlphaMode};
+use crate::mesh::{Mesh, Triangle, Vertex};
+use crate::camera::Camera;
+use image::RgbaImage;
+use nalgebra::{Matrix3, Matrix4, Point3};
+
+pub struct GltfScene {
+    pub meshes: Vec<Mesh>,
+    pub cameras: Vec<Camera>,
+}
+
+// Camera transforms are collected while walking the node tree and turned into
+// `Camera`s once the mesh bounds are known
+struct CameraNode {
+    transform: Matrix4<f32>,
+    projection: CameraProjection,
+}
+
+enum CameraProjection {
+    Perspective { yfov: f32 },
+    Orthographic { ymag: f32 }, // Half the height of the view
+}
+
+pub fn load_gltf(
+    path: &str,
+    aspect_ratio: f32,
+    materials: &mut Vec<Material>,
+    texture_manager: &mut TextureManager,
+) -> Result<GltfScene, Box<dyn std::error::Error>> {
+    // Buffers and images are resolved relative to the file, so no network access is needed
+    let (document, buffers, images) = gltf::import(path)?;
+
+    let texture_ids: Vec<String> = (0..images.len())
+        .map(|index| format!("{}#image{}", path, index))
+        .collect();
+    for (image, texture_id) in images.iter().zip(&texture_ids) {
+        texture_manager.insert_texture(texture_id, convert_image(image)?);
+    }
+
+    let material_offset = materials.len();
+    for gltf_material in document.materials() {
+        materials.push(convert_material(&gltf_material, &texture_ids));
+    }
+    let mut default_material = None;
+
+    let mut meshes = Vec::new();
+    let mut camera_nodes = Vec::new();
+
+    let scene = document.default_scene().or_else(|| document.scenes().next());
+    if let Some(scene) = scene {
+        let mut stack: Vec<(gltf::Node, Matrix4<f32>)> = scene.nodes()
+            .map(|node| (node, Matrix4::identity()))
+            .collect();
+
+        while let Some((node, parent_transform)) = stack.pop() {
+            let transform = parent_transform * Matrix4::from(node.transform().matrix());
+
+            if let Some(gltf_mesh) = node.mesh() {
+                for primitive in gltf_mesh.primitives() {
+                    if primitive.mode() != gltf::mesh::Mode::Triangles {
+                        continue;
+                    }
+
+                    let material_index = match primitive.material().index() {
+                        Some(index) => material_offset + index,
+                        None => *default_material.get_or_insert_with(|| {
+                            materials.push(Material::new("gltf_default"));
+                            materials.len() - 1
+                        }),
+                    };
+
+                    if let Some(mesh) = convert_primitive(&primitive, &buffers, &transform, material_index) {
+                        meshes.push(mesh);
+                    }
+                }
+            }
+
+            if let Some(camera) = node.camera() {
+                let projection = match camera.projection() {
+                    gltf::camera::Projection::Perspective(perspective) => {
+                        CameraProjection::Perspective { yfov: perspective.yfov() }
+                    }
+                    gltf::camera::Projection::Orthographic(orthographic) => {
+                        CameraProjection::Orthographic { ymag: orthographic.ymag() }
+                    }
+                };
+                camera_nodes.push(CameraNode { transform, projection });
+            }
+
+            for child in node.children() {
+                stack.push((child, transform));
+            }
+        }
+    }
+
+    let scene_center = scene_center(&meshes);
+    let cameras = camera_nodes.iter()
+        .map(|camera_node| convert_camera(camera_node, scene_center, aspect_ratio))
+        .collect();
+
+    Ok(GltfScene { meshes, cameras })
+}
+
+fn convert_image(image: &gltf::image::Data) -> Result<RgbaImage, Box<dyn std::error::Error>> {
+    use gltf::image::Format;
+
+    let (channels, bytes_per_channel) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        other => return Err(format!("unsupported glTF image format {:?}", other).into()),
+    };
+
+    let pixel_count = (image.width * image.height) as usize;
+    let mut rgba = Vec::with_capacity(pixel_count * 4);
+    for pixel in image.pixels.chunks_exact(channels * bytes_per_channel).take(pixel_count) {
+        // Keep the most significant byte of 16-bit channels (stored little endian)
+        let channel = |c: usize| pixel[c * bytes_per_channel + bytes_per_channel - 1];
+        let (r, g, b, a) = match channels {
+            1 => (channel(0), channel(0), channel(0), 255),
+            2 => (channel(0), channel(0), channel(0), channel(1)),
+            3 => (channel(0), channel(1), channel(2), 255),
+            _ => (channel(0), channel(1), channel(2), channel(3)),
+        };
+        rgba.extend_from_slice(&[r, g, b, a]);
+    }
+
+    RgbaImage::from_raw(image.width, image.height, rgba)
+        .ok_or_else(|| "glTF image data is truncated".into())
+}
+
+fn convert_material(gltf_material: &gltf::Material, texture_ids: &[String]) -> Material {
+    let name = gltf_material.name().unwrap_or("gltf_material");
+    let pbr = gltf_material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+
+    let texture_id = |info: gltf::Texture| texture_ids.get(info.source().index()).cloned();
+
+    let metallic = pbr.metallic_factor();
+    let roughness = pbr.roughness_factor();
+
+    // Dielectrics keep a 4% specular reflectance, metals reflect their base color
+    let mut material = Material::new(name)
+        .with_albedo(r, g, b)
+        .with_metallic(metallic)
+        .with_specular_f0(0.04);
+    material.roughness = roughness;
+
+    if let Some(info) = pbr.base_color_texture() {
+        material.texture_id = texture_id(info.texture());
+    }
+
+    if let Some(info) = pbr.metallic_roughness_texture() {
+        material.metallic_roughness_texture_id = texture_id(info.texture());
+    }
+
+    if let Some(normal) = gltf_material.normal_texture() {
+        material.normal_texture_id = texture_id(normal.texture());
+    }
+
+    material.alpha_mode = match gltf_material.alpha_mode() {
+        gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+        gltf::material::AlphaMode::Mask => AlphaMode::Cutout(gltf_material.alpha_cutoff().unwrap_or(0.5)),
+        gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+    };
+
+    let [er, eg, eb] = gltf_material.emissive_factor();
+    material = material.with_emission(er, eg, eb);
+    if let Some(info) = gltf_material.emissive_texture() {
+        material.emissive_texture_id = texture_id(info.texture());
+    }
+
+    material
+}
+
+fn convert_primitive(
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+    transform: &Matrix4<f32>,
+    material_index: usize,
+) -> Option<Mesh> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    let positions: Vec<Vec3> = reader.read_positions()?
+        .map(|[x, y, z]| transform.transform_point(&Point3::new(x, y, z)).coords)
+        .collect();
+
+    // Normals are transformed by the inverse transpose to survive non-uniform scaling
+    let normal_matrix = transform.fixed_view::<3, 3>(0, 0)
+        .try_inverse()
+        .map(|inverse| inverse.transpose())
+        .unwrap_or_else(Matrix3::identity);
+    let normals: Vec<Vec3> = match reader.read_normals() {
+        Some(normals) => normals.map(|[x, y, z]| (normal_matrix * Vec3::new(x, y, z)).normalize()).collect(),
+        None => vec![Vec3::zeros(); positions.len()],
+    };
+
+    let tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+        Some(tex_coords) => tex_coords.into_f32().collect(),
+        None => vec![[0.0, 0.0]; positions.len()],
+    };
+
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+
+    let vertex = |index: u32| -> Option<Vertex> {
+        let i = index as usize;
+        Some(Vertex {
+            position: *positions.get(i)?,
+            normal: normals.get(i).copied().unwrap_or_else(Vec3::zeros),
+            u: tex_coords.get(i).map_or(0.0, |uv| uv[0]),
+            v: tex_coords.get(i).map_or(0.0, |uv| uv[1]),
+        })
+    };
+
+    let triangles: Vec<Triangle> = indices
+        .chunks_exact(3)
+        .filter_map(|face| Some(Triangle::new(vertex(face[0])?, vertex(face[1])?, vertex(face[2])?)))
+        .collect();
+
+    if triangles.is_empty() {
+        None
+    } else {
+        Some(Mesh::new(triangles, material_index))
+    }
+}
+
+fn scene_center(meshes: &[Mesh]) -> Option<Point3f> {
+    let first = meshes.first()?;
+    let mut min = first.bounds_min;
+    let mut max = first.bounds_max;
+    for mesh in meshes {
+        min = min.inf(&mesh.bounds_min);
+        max = max.sup(&mesh.bounds_max);
+    }
+    Some(nalgebra::center(&min, &max))
+}
+
+fn convert_camera(camera_node: &CameraNode, scene_center: Option<Point3f>, aspect_ratio: f32) -> Camera {
+    // glTF cameras look down their local -Z axis, with +Y up
+    let position = camera_node.transform.transform_point(&Point3::origin());
+    let forward = camera_node.transform
+        .transform_vector(&Vec3::new(0.0, 0.0, -1.0))
+        .normalize();
+    let up = camera_node.transform
+        .transform_vector(&Vec3::new(0.0, 1.0, 0.0))
+        .normalize();
+
+    // Orbit around the point in front of the camera closest to the scene center
+    let focus_distance = scene_center
+        .map(|center| (center - position).dot(&forward))
+        .filter(|distance| *distance > 0.0)
+        .unwrap_or(10.0);
+
+    // Orthographic views get the field of view that frames the same height at the focus
+    let (yfov, orthographic) = match camera_node.projection {
+        CameraProjection::Perspective { yfov } => (yfov, None),
+        CameraProjection::Orthographic { ymag } => (2.0 * (ymag / focus_distance).atan(), Some(ymag)),
+    };
+
+    let mut camera = Camera::look_at(
+        position,
+        position + forward * focus_distance,
+        up,
+        yfov.to_degrees(),
+        aspect_ratio,
+    );
+    camera.orthographic = orthographic;
+    camera
+}
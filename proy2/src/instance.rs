@@ -0,0 +1,85 @@
+use crate::math_utils::{Vec3, Point3f, Ray, ray_hits_aabb};
+use crate::cube::{HitRecord, Scene};
+use nalgebra::{Matrix3, Matrix4, Point3, Rotation3};
+use std::sync::Arc;
+
+// A shared piece of geometry placed in the scene with its own affine transform
+pub struct Instance {
+    pub geometry: Arc<Scene>,
+    transform: Matrix4<f32>,
+    inverse: Matrix4<f32>,
+    normal_matrix: Matrix3<f32>,
+    bounds: Option<(Point3f, Point3f)>,
+}
+
+impl Instance {
+    pub fn new(geometry: Arc<Scene>, transform: Matrix4<f32>) -> Self {
+        let inverse = transform.try_inverse().unwrap_or_else(Matrix4::identity);
+        let normal_matrix = inverse.fixed_view::<3, 3>(0, 0).transpose();
+        let bounds = geometry.bounds().map(|(min, max)| transform_bounds(&transform, min, max));
+
+        Self { geometry, transform, inverse, normal_matrix, bounds }
+    }
+
+    // Rotation is given as Euler angles in degrees (roll, pitch, yaw around X, Y, Z)
+    pub fn from_trs(geometry: Arc<Scene>, translation: Vec3, rotation: Vec3, scale: Vec3) -> Self {
+        let rotation = Rotation3::from_euler_angles(
+            rotation.x.to_radians(),
+            rotation.y.to_radians(),
+            rotation.z.to_radians(),
+        );
+        let transform = Matrix4::new_translation(&translation)
+            * rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&scale);
+
+        Self::new(geometry, transform)
+    }
+
+    pub fn bounds(&self) -> Option<(Point3f, Point3f)> {
+        self.bounds
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (min, max) = self.bounds?;
+        if !ray_hits_aabb(ray, &min, &max, t_min, t_max) {
+            return None;
+        }
+
+        // Object space rays are renormalized, so distances are rescaled by the direction length
+        let local_origin = self.inverse.transform_point(&ray.origin);
+        let local_direction = self.inverse.transform_vector(&ray.direction);
+        let scale = local_direction.magnitude();
+        if scale == 0.0 {
+            return None;
+        }
+        let local_ray = Ray::new(local_origin, local_direction);
+
+        let mut hit = self.geometry.hit(&local_ray, t_min * scale, t_max * scale)?;
+        hit.t /= scale;
+        hit.point = ray.at(hit.t).coords;
+        // The local normal already faces the ray, and the inverse transpose keeps it that way
+        hit.normal = (self.normal_matrix * hit.normal).normalize();
+        // Tangents lie in the surface and follow the transform itself
+        hit.tangent = self.transform.transform_vector(&hit.tangent).normalize();
+        hit.bitangent = self.transform.transform_vector(&hit.bitangent).normalize();
+        Some(hit)
+    }
+}
+
+fn transform_bounds(transform: &Matrix4<f32>, min: Point3f, max: Point3f) -> (Point3f, Point3f) {
+    let mut world_min = Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut world_max = Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+
+    for corner in 0..8 {
+        let local = Point3::new(
+            if corner & 1 == 0 { min.x } else { max.x },
+            if corner & 2 == 0 { min.y } else { max.y },
+            if corner & 4 == 0 { min.z } else { max.z },
+        );
+        let world = transform.transform_point(&local);
+        world_min = world_min.inf(&world);
+        world_max = world_max.sup(&world);
+    }
+
+    (world_min, world_max)
+}
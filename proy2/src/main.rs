@@ -1,17 +1,49 @@
 mod math_utils;
 mod materials;
+mod noise;
+mod procedural;
+mod bsdf;
+mod spectrum;
+mod medium;
 mod cube;
 mod camera;
 mod skybox;
 mod raytracer;
+mod mesh;
+mod instance;
+mod quad;
+mod block_models;
+mod obj_loader;
+mod gltf_loader;
+mod vox_loader;
+mod nbt;
+mod structure_loader;
+mod resource_pack;
+mod sampler;
+mod film;
 
 use raylib::prelude::*;
 use math_utils::{Vec3, Color, Point3f};
-use materials::{create_materials, TextureManager};
+use materials::{create_materials, Biome, TextureManager, TextureFilter, Tint};
 use cube::{Cube, Scene};
 use camera::Camera;
-use raytracer::Raytracer;
+use instance::Instance;
+use raytracer::{MisHeuristic, Raytracer, sample_heatmap};
+use medium::{FogVolume, HomogeneousMedium};
+use film::Filter;
+use structure_loader::{BlockFaces, BlockMaterialTable};
 use nalgebra::Point3;
+use std::sync::Arc;
+
+// Climates the B key cycles through for materials tinted by a colormap
+const BIOMES: [(&str, Biome); 6] = [
+    ("plains", Biome::PLAINS),
+    ("forest", Biome::FOREST),
+    ("jungle", Biome::JUNGLE),
+    ("taiga", Biome::TAIGA),
+    ("savanna", Biome::SAVANNA),
+    ("snowy plains", Biome::SNOWY_PLAINS),
+];
 
 fn create_scene() -> (Scene, Vec<materials::Material>) {
     let mut scene = Scene::new();
@@ -23,16 +55,21 @@ fn create_scene() -> (Scene, Vec<materials::Material>) {
     let iron_material = 2;
     let diamond_material = 3;
     let water_material = 4;
+    let grass_top_material = 5;
+    let dirt_material = 6;
+    let leaves_material = 7;
+    let slime_material = 8;
+    let snow_material = 9;
     
     // Ground layer (grass blocks)
     for x in -3..4 {
         for z in -3..4 {
             let distance_from_center = ((x * x + z * z) as f32).sqrt();
             if distance_from_center <= 3.5 {
-                scene.add_cube(Cube::new(
+                scene.add_cube(Cube::with_face_materials(
                     Point3::new(x as f32, -1.0, z as f32),
                     Point3::new(x as f32 + 1.0, 0.0, z as f32 + 1.0),
-                    grass_material,
+                    [grass_material, grass_material, grass_top_material, dirt_material, grass_material, grass_material],
                 ));
             }
         }
@@ -59,32 +96,60 @@ fn create_scene() -> (Scene, Vec<materials::Material>) {
         diamond_material,
     ));
     
-    // Glass windows/barriers
-    scene.add_cube(Cube::new(
-        Point3::new(-1.0, 0.5, 2.0),
-        Point3::new(2.0, 1.5, 2.1),
+    // Glass windows/barriers, one pane placed twice, the second turned to run along z
+    let mut pane = Scene::new();
+    pane.add_cube(Cube::new(
+        Point3::new(0.0, 0.5, 0.0),
+        Point3::new(3.0, 1.5, 0.1),
         glass_material,
     ));
+    let pane = Arc::new(pane);
+    let unit = Vec3::new(1.0, 1.0, 1.0);
+    scene.add_instance(Instance::from_trs(pane.clone(), Vec3::new(-1.0, 0.0, 2.0), Vec3::zeros(), unit));
+    scene.add_instance(Instance::from_trs(pane, Vec3::new(2.0, 0.0, 2.0), Vec3::new(0.0, 90.0, 0.0), unit));
     
+    // Leaves, slime and snow, lit through and from below their surfaces
     scene.add_cube(Cube::new(
-        Point3::new(2.0, 0.5, -1.0),
-        Point3::new(2.1, 1.5, 2.0),
-        glass_material,
+        Point3::new(-3.0, 0.0, 0.0),
+        Point3::new(-2.0, 1.0, 1.0),
+        leaves_material,
+    ));
+    
+    scene.add_cube(Cube::new(
+        Point3::new(0.0, 0.0, -3.0),
+        Point3::new(1.0, 1.0, -2.0),
+        slime_material,
+    ));
+    
+    scene.add_cube(Cube::new(
+        Point3::new(-2.0, 0.0, 1.5),
+        Point3::new(-1.0, 1.0, 2.5),
+        snow_material,
     ));
     
     (scene, materials)
 }
 
-fn setup_raytracer() -> Result<Raytracer, Box<dyn std::error::Error>> {
+// Also returns the cameras of imported scenes
+fn setup_raytracer(aspect_ratio: f32) -> Result<(Raytracer, Vec<Camera>), Box<dyn std::error::Error>> {
     let mut raytracer = Raytracer::new();
     
     // Load textures
     raytracer.load_texture("grass_side", "assets/textures/grass_side_carried.png")?;
+    raytracer.load_texture("grass_top", "assets/textures/grass_top.png")?;
+    raytracer.load_texture("dirt", "assets/textures/dirt.png")?;
     raytracer.load_texture("glass", "assets/textures/glass.png")?;
     raytracer.load_texture("iron_block", "assets/textures/iron_block.png")?;
     raytracer.load_texture("diamond_block", "assets/textures/diamond_block.png")?;
     raytracer.load_texture("water_still", "assets/textures/water_still.png")?;
     
+    // Procedural textures, no image files needed
+    raytracer.add_texture("marble", procedural::marble());
+    raytracer.add_texture("stone", procedural::stone());
+    raytracer.add_texture("terrain", procedural::terrain());
+    raytracer.add_texture("mossy_stone", procedural::mossy_stone());
+    raytracer.add_texture("tiles", procedural::tiles());
+    
     // Create scene and materials
     let (scene, materials) = create_scene();
     
@@ -93,11 +158,82 @@ fn setup_raytracer() -> Result<Raytracer, Box<dyn std::error::Error>> {
         raytracer.add_material(material);
     }
     
+    raytracer.fog_volumes.push(diorama_haze());
+    
     raytracer.scene = scene;
+    
+    // Models and resource packs given on the command line
+    let mut cameras = Vec::new();
+    for path in std::env::args().skip(1) {
+        cameras.extend(load_argument(&mut raytracer, &path, aspect_ratio)?);
+    }
+    
     raytracer.samples_per_pixel = 2; // Lower for real-time performance
     raytracer.max_depth = 5;
     
-    Ok(raytracer)
+    Ok((raytracer, cameras))
+}
+
+// Adds a model file to the diorama by its extension, standing on the ground at the center.
+// Anything else is taken for a resource pack, a directory or zip of block textures.
+// Light haze over the diorama, so sunlight shows as shafts past the glass and the iron block
+fn diorama_haze() -> FogVolume {
+    FogVolume::region(
+        Point3::new(-8.0, -1.0, -8.0),
+        Point3::new(8.0, 8.0, 8.0),
+        HomogeneousMedium::new(Color::repeat(0.005), Color::repeat(0.03), 0.6),
+    )
+}
+
+fn load_argument(raytracer: &mut Raytracer, path: &str, aspect_ratio: f32) -> Result<Vec<Camera>, Box<dyn std::error::Error>> {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    let cameras = match extension.as_deref() {
+        Some("obj") => {
+            raytracer.load_obj(path, Vec3::new(0.0, 0.0, 0.0), 1.0)?;
+            Vec::new()
+        }
+        Some("gltf" | "glb") => raytracer.load_gltf(path, aspect_ratio)?,
+        Some("vox") => {
+            // A voxel per texture pixel, so models match the blocks around them
+            raytracer.load_vox(path, Vec3::new(0.0, 0.0, 0.0), 1.0 / 16.0)?;
+            Vec::new()
+        }
+        Some("nbt" | "schem") => {
+            let table = block_materials(&raytracer.materials);
+            raytracer.load_structure(path, Vec3::new(0.0, 0.0, 0.0), &table)?;
+            Vec::new()
+        }
+        _ => {
+            let changed = raytracer.load_resource_pack(path)?;
+            println!("Resource pack {} applied to {} materials", path, changed);
+            return Ok(Vec::new());
+        }
+    };
+    println!("Model {} added to the scene", path);
+    Ok(cameras)
+}
+
+// Blocks of the game drawn with the diorama's materials; anything else becomes stone
+fn block_materials(materials: &[materials::Material]) -> BlockMaterialTable {
+    let index = |name: &str| materials.iter().position(|material| material.name == name).unwrap_or(0);
+    BlockMaterialTable::new()
+        .with_block("grass_block", BlockFaces::new(index("grass_top"), index("dirt"), index("grass")))
+        .with_block("dirt", BlockFaces::uniform(index("dirt")))
+        .with_block("glass", BlockFaces::uniform(index("glass")))
+        .with_block("glass_pane", BlockFaces::uniform(index("glass")))
+        .with_block("iron_block", BlockFaces::uniform(index("iron")))
+        .with_block("diamond_block", BlockFaces::uniform(index("diamond")))
+        .with_block("water", BlockFaces::uniform(index("water")))
+        .with_block("oak_leaves", BlockFaces::uniform(index("leaves")))
+        .with_block("slime_block", BlockFaces::uniform(index("slime")))
+        .with_block("snow_block", BlockFaces::uniform(index("snow")))
+        .with_block("quartz_block", BlockFaces::uniform(index("marble")))
+        .with_block("mossy_cobblestone", BlockFaces::uniform(index("mossy_stone")))
+        .with_block("mossy_stone_bricks", BlockFaces::uniform(index("mossy_stone")))
+        .with_fallback(BlockFaces::uniform(index("stone")))
 }
 
 fn color_to_raylib_color(color: Color) -> raylib::prelude::Color {
@@ -119,15 +255,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     rl.set_target_fps(30);
     
     // Setup raytracer
-    let raytracer = setup_raytracer()?;
+    let aspect_ratio = screen_width as f32 / screen_height as f32;
+    let (mut raytracer, cameras) = setup_raytracer(aspect_ratio)?;
     
-    // Setup camera
-    let mut camera = Camera::new(
+    // Setup camera, starting from the first imported one if there is any
+    let mut camera = cameras.into_iter().next().unwrap_or_else(|| Camera::new(
         Point3::new(0.0, 0.0, 0.0),  // Target center
         10.0,                        // Distance
         45.0,                        // FOV
-        screen_width as f32 / screen_height as f32, // Aspect ratio
-    );
+        aspect_ratio,
+    ));
     
     // Create texture for rendered image
     let render_width = 200;  // Lower resolution for real-time performance
@@ -137,12 +274,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     let mut last_render_time = std::time::Instant::now();
     let mut auto_rotate = true;
+    let mut show_heatmap = false;
+    let mut biome_index = 0;
+    let scene_clock = std::time::Instant::now();
     
     println!("Controls:");
     println!("- Mouse: Rotate camera");
     println!("- Mouse Wheel: Zoom in/out");
     println!("- SPACE: Toggle auto-rotation");
     println!("- R: Re-render scene");
+    println!("- F: Cycle texture filtering");
+    println!("- S: Toggle spectral rendering (dispersion in diamond, glass and water)");
+    println!("- M: Cycle samplers");
+    println!("- N: Toggle adaptive sampling");
+    println!("- H: Toggle heatmap of samples per pixel");
+    println!("- P: Cycle pixel reconstruction filters");
+    println!("- [ / ]: Shrink or widen the pixel filter");
+    println!("- I: Toggle balance and power heuristics for sunlight");
+    println!("- G: Cycle haze over the diorama, everywhere or off");
+    println!("- W: Toggle wet surfaces");
+    println!("- B: Cycle biomes (grass colormap from a resource pack)");
     println!("- ESC: Exit");
     
     while !rl.window_should_close() {
@@ -155,6 +306,104 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
         }
         
+        if rl.is_key_pressed(KeyboardKey::KEY_F) {
+            raytracer.texture_manager.filter = match raytracer.texture_manager.filter {
+                TextureFilter::Nearest => TextureFilter::Bilinear,
+                TextureFilter::Bilinear => TextureFilter::Trilinear,
+                TextureFilter::Trilinear => TextureFilter::Nearest,
+            };
+            println!("Texture filtering: {:?}", raytracer.texture_manager.filter);
+            last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+        
+        if rl.is_key_pressed(KeyboardKey::KEY_S) {
+            raytracer.spectral = !raytracer.spectral;
+            println!("Spectral rendering: {}", if raytracer.spectral { "ON" } else { "OFF" });
+            last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+        
+        if rl.is_key_pressed(KeyboardKey::KEY_M) {
+            raytracer.sampler = raytracer.sampler.next();
+            println!("Sampler: {:?}", raytracer.sampler);
+            last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+        
+        if rl.is_key_pressed(KeyboardKey::KEY_N) {
+            // Adaptive sampling needs a budget above its first pass to move samples around
+            let adaptive = raytracer.noise_threshold == 0.0;
+            raytracer.noise_threshold = if adaptive { 0.02 } else { 0.0 };
+            raytracer.samples_per_pixel = if adaptive { 8 } else { 2 };
+            println!("Adaptive sampling: {}", if adaptive { "ON" } else { "OFF" });
+            last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+        
+        if rl.is_key_pressed(KeyboardKey::KEY_H) {
+            show_heatmap = !show_heatmap;
+            println!("Sample heatmap: {}", if show_heatmap { "ON" } else { "OFF" });
+            last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+        
+        if rl.is_key_pressed(KeyboardKey::KEY_P) {
+            raytracer.filter = Filter::new(raytracer.filter.kind.next());
+            println!("Pixel filter: {:?} (radius {})", raytracer.filter.kind, raytracer.filter.radius);
+            last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+        
+        for (key, step) in [(KeyboardKey::KEY_LEFT_BRACKET, -0.5), (KeyboardKey::KEY_RIGHT_BRACKET, 0.5)] {
+            if rl.is_key_pressed(key) {
+                // The film keeps a footprint per pixel that grows with the square of the radius
+                raytracer.filter = raytracer.filter.with_radius((raytracer.filter.radius + step).min(4.0));
+                println!("Pixel filter: {:?} (radius {})", raytracer.filter.kind, raytracer.filter.radius);
+                last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+            }
+        }
+        
+        if rl.is_key_pressed(KeyboardKey::KEY_I) {
+            raytracer.mis_heuristic = match raytracer.mis_heuristic {
+                MisHeuristic::Balance => MisHeuristic::Power,
+                MisHeuristic::Power => MisHeuristic::Balance,
+            };
+            println!("MIS heuristic: {:?}", raytracer.mis_heuristic);
+            last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+        
+        if rl.is_key_pressed(KeyboardKey::KEY_G) {
+            // Haze everywhere also covers models loaded beyond the diorama
+            raytracer.fog_volumes = match raytracer.fog_volumes.first() {
+                Some(FogVolume { bounds: Some(_), medium }) => vec![FogVolume::global(*medium)],
+                Some(_) => Vec::new(),
+                None => vec![diorama_haze()],
+            };
+            let haze = match raytracer.fog_volumes.first() {
+                Some(FogVolume { bounds: Some(_), .. }) => "diorama",
+                Some(_) => "everywhere",
+                None => "off",
+            };
+            println!("Haze: {}", haze);
+            last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+        
+        if rl.is_key_pressed(KeyboardKey::KEY_W) {
+            let wetness = if raytracer.materials.first().is_some_and(|material| material.wetness > 0.0) { 0.0 } else { 1.0 };
+            for material in &mut raytracer.materials {
+                material.wetness = wetness;
+            }
+            println!("Wet surfaces: {}", if wetness > 0.0 { "ON" } else { "OFF" });
+            last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+        
+        if rl.is_key_pressed(KeyboardKey::KEY_B) {
+            biome_index = (biome_index + 1) % BIOMES.len();
+            let (name, biome) = BIOMES[biome_index];
+            for material in &mut raytracer.materials {
+                if let Some(Tint::ColorMap { biome: material_biome, .. }) = &mut material.tint {
+                    *material_biome = biome;
+                }
+            }
+            println!("Biome: {}", name);
+            last_render_time = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        }
+        
         // Camera controls
         if rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT) {
             let mouse_delta = rl.get_mouse_delta();
@@ -182,7 +431,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("Rendering frame...");
             let start_time = std::time::Instant::now();
             
-            let pixels = raytracer.render(&camera, render_width as u32, render_height as u32);
+            // Animated textures follow the wall clock
+            raytracer.set_time(scene_clock.elapsed().as_secs_f32());
+            let (pixels, samples) = raytracer.render_with_samples(&camera, render_width as u32, render_height as u32);
+            let pixels = if show_heatmap { sample_heatmap(&samples) } else { pixels };
             
             // Update texture
             for y in 0..render_height {
@@ -1,17 +1,134 @@
-use crate::math_utils::{Vec3, Color, Ray, reflect, refract, fresnel, random_in_unit_sphere};
+use crate::math_utils::{Vec3, Color, Ray, EPSILON};
+use crate::cube::HitRecord;
+use crate::spectrum::{Dispersion, Wavelengths};
+use crate::medium::HomogeneousMedium;
+use crate::bsdf::{Bsdf, Conductor, Dielectric, Frame, Ggx, Lambertian, Mix, Plastic, SurfaceBsdf, Translucent};
 use image::{DynamicImage, RgbaImage};
+use image::imageops::FilterType;
+use nalgebra::Vector4;
 use std::collections::HashMap;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    Cutout(f32), // Texels with alpha below the threshold are skipped entirely
+    Blend,       // Texel alpha is the probability of the surface being hit
+}
+
+// Channel layout of tangent-space normal maps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NormalMapFormat {
+    OpenGl, // Green points up the image, used by glTF and Blender
+    LabPbr, // Normal in red and green with green pointing down, ambient occlusion in blue and height in alpha
+}
+
+// Metals with hardcoded optical data in LabPBR, indexed by green channel value minus 230.
+// Reflectance at normal incidence in linear RGB.
+const LABPBR_METALS: [Color; 8] = [
+    Color::new(0.56, 0.57, 0.58), // Iron
+    Color::new(1.00, 0.71, 0.29), // Gold
+    Color::new(0.91, 0.92, 0.92), // Aluminum
+    Color::new(0.55, 0.56, 0.55), // Chrome
+    Color::new(0.95, 0.64, 0.54), // Copper
+    Color::new(0.63, 0.63, 0.64), // Lead
+    Color::new(0.67, 0.64, 0.59), // Platinum
+    Color::new(0.95, 0.93, 0.88), // Silver
+];
+
+// Physical surface properties at one texel, from the specular texture when there is one
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceSample {
+    pub roughness: f32,   // Perceptual roughness, 1 - smoothness
+    pub metalness: f32,   // Share of the metal lobe
+    pub f0: Color,        // Metal reflectance at normal incidence
+    pub specular_f0: f32, // Reflectance of the clear coat of non-metals at normal incidence
+    pub porosity: f32,    // Share of rain soaked up by the surface rather than left on top
+    pub subsurface: f32,  // Amount of light scattered below the surface
+    pub emission: f32,    // Emission strength relative to the base color
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biome {
+    pub temperature: f32,
+    pub humidity: f32,
+}
+
+impl Biome {
+    pub const PLAINS: Biome = Biome { temperature: 0.8, humidity: 0.4 };
+    pub const FOREST: Biome = Biome { temperature: 0.7, humidity: 0.8 };
+    pub const JUNGLE: Biome = Biome { temperature: 0.95, humidity: 0.9 };
+    pub const TAIGA: Biome = Biome { temperature: 0.25, humidity: 0.8 };
+    pub const SAVANNA: Biome = Biome { temperature: 2.0, humidity: 0.0 };
+    pub const SNOWY_PLAINS: Biome = Biome { temperature: 0.0, humidity: 0.5 };
+}
+
+// Color multiplied onto grayscale grass, foliage and water textures
+#[derive(Debug, Clone)]
+pub enum Tint {
+    Fixed(Color),
+    // Minecraft colormap (grass.png, foliage.png) looked up by biome climate
+    ColorMap { texture_id: String, biome: Biome },
+}
+
+impl Tint {
+    pub fn color(&self, texture_manager: &TextureManager, colormap: Option<TextureHandle>) -> Color {
+        match (self, colormap) {
+            (Tint::Fixed(color), _) => *color,
+            (Tint::ColorMap { biome, .. }, Some(colormap)) => {
+                // Same triangle lookup as the game: humidity is scaled by temperature
+                let temperature = biome.temperature.clamp(0.0, 1.0);
+                let humidity = biome.humidity.clamp(0.0, 1.0) * temperature;
+                let u = (1.0 - temperature).min(0.999);
+                let v = (1.0 - humidity).min(0.999);
+                texture_manager.sample_texture(colormap, &TexCoords::new(u, v, Vec3::zeros()), 0.0).0
+            }
+            (Tint::ColorMap { .. }, None) => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+// Texture names of a material resolved to handles, so sampling never looks up strings
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MaterialTextures {
+    pub base: Option<TextureHandle>,
+    pub overlay: Option<TextureHandle>,
+    pub emissive: Option<TextureHandle>,
+    pub normal: Option<TextureHandle>,
+    pub height: Option<TextureHandle>,
+    pub specular: Option<TextureHandle>,
+    pub metallic_roughness: Option<TextureHandle>,
+    pub colormap: Option<TextureHandle>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Material {
     pub name: String,
     pub albedo: Color,
-    pub specular: f32,
-    pub transparency: f32,
-    pub reflectivity: f32,
+    pub specular_f0: f32,  // Reflectance of non-metals at normal incidence, about 0.02 to 0.05
+    pub transparency: f32, // Share of light refracted through the surface when the index is not 1
+    pub metallic: f32,     // Share of the metal lobe; metals reflect their base color
     pub refractive_index: f32,
+    pub absorption: Color, // Beer-Lambert coefficients inside the medium, per block travelled
+    pub scattering: Color, // Light scattered inside the medium per block travelled, like murky water
+    pub anisotropy: f32,   // Henyey-Greenstein g of that scattering
+    pub subsurface_radius: Color, // Mean free path inside, per channel; replaces the two above when set
+    pub translucency: f32, // Share of diffuse light passing through thin surfaces like leaves
+    pub dispersion: Option<Dispersion>, // Replaces `refractive_index` when rendering spectrally
     pub roughness: f32,
+    pub wetness: f32, // Rain on the surface: porous texels soak it up and darken, the rest get smoother
     pub texture_id: Option<String>,
+    pub emission: Color,
+    pub emissive_texture_id: Option<String>,
+    pub normal_texture_id: Option<String>,
+    pub normal_map_format: NormalMapFormat,
+    pub height_texture_id: Option<String>, // Red channel, white is the top of the surface
+    pub parallax_depth: f32,               // Depth of a black height texel, in texture repeats
+    pub specular_texture_id: Option<String>, // LabPBR `_s` layout: smoothness, F0 or metal, porosity, emission
+    pub metallic_roughness_texture_id: Option<String>, // glTF layout: roughness in green, metalness in blue
+    pub alpha_mode: AlphaMode,
+    pub tint: Option<Tint>,
+    pub overlay_texture_id: Option<String>, // Tinted layer over the base texture, like grass_side_overlay
+    pub textures: MaterialTextures,
 }
 
 impl Material {
@@ -19,12 +136,31 @@ impl Material {
         Self {
             name: name.to_string(),
             albedo: Color::new(0.8, 0.8, 0.8),
-            specular: 0.1,
+            specular_f0: 0.04,
             transparency: 0.0,
-            reflectivity: 0.1,
+            metallic: 0.0,
             refractive_index: 1.0,
+            absorption: Color::zeros(),
+            scattering: Color::zeros(),
+            anisotropy: 0.0,
+            subsurface_radius: Color::zeros(),
+            translucency: 0.0,
+            dispersion: None,
             roughness: 0.5,
+            wetness: 0.0,
             texture_id: None,
+            emission: Color::zeros(),
+            emissive_texture_id: None,
+            normal_texture_id: None,
+            normal_map_format: NormalMapFormat::OpenGl,
+            height_texture_id: None,
+            parallax_depth: 0.0,
+            specular_texture_id: None,
+            metallic_roughness_texture_id: None,
+            alpha_mode: AlphaMode::Opaque,
+            tint: None,
+            overlay_texture_id: None,
+            textures: MaterialTextures::default(),
         }
     }
     
@@ -38,170 +174,869 @@ impl Material {
         self
     }
     
-    pub fn with_properties(mut self, specular: f32, transparency: f32, reflectivity: f32, refractive_index: f32) -> Self {
-        self.specular = specular;
+    pub fn with_emission(mut self, r: f32, g: f32, b: f32) -> Self {
+        self.emission = Color::new(r, g, b);
+        self
+    }
+    
+    // Refracted light is then tinted by the distance travelled inside instead of the base color
+    pub fn with_absorption(mut self, r: f32, g: f32, b: f32) -> Self {
+        self.absorption = Color::new(r, g, b);
+        self
+    }
+    
+    pub fn with_scattering(mut self, r: f32, g: f32, b: f32, anisotropy: f32) -> Self {
+        self.scattering = Color::new(r, g, b);
+        self.anisotropy = anisotropy;
+        self
+    }
+    
+    // Light refracted into the material takes a random walk until it finds its way out.
+    // It travels `r, g, b` blocks between scattering events on average and the albedo sets how
+    // much of it comes back out. Needs transparency and a refractive index to get in at all.
+    pub fn with_subsurface(mut self, r: f32, g: f32, b: f32) -> Self {
+        self.subsurface_radius = Color::new(r, g, b);
+        self
+    }
+    
+    // Thin surfaces let diffuse light through to the other side without refracting
+    pub fn with_translucency(mut self, translucency: f32) -> Self {
+        self.translucency = translucency;
+        self
+    }
+    
+    // Rays refracted through the surface travel inside the material
+    pub fn has_volume(&self) -> bool {
+        self.transparency > 0.0 && self.refractive_index != 1.0
+    }
+    
+    // What rays travel through after refracting into the material
+    pub fn interior(&self) -> HomogeneousMedium {
+        if self.subsurface_radius == Color::zeros() {
+            return HomogeneousMedium::new(self.absorption, self.scattering, self.anisotropy);
+        }
+        let extinction = self.subsurface_radius.map(|radius| 1.0 / radius.max(EPSILON));
+        let albedo = self.albedo.map(single_scattering_albedo);
+        let scattering = extinction.component_mul(&albedo);
+        HomogeneousMedium::new(extinction - scattering, scattering, self.anisotropy)
+    }
+    
+    pub fn with_dispersion(mut self, dispersion: Dispersion) -> Self {
+        self.dispersion = Some(dispersion);
+        self
+    }
+    
+    pub fn with_alpha_mode(mut self, alpha_mode: AlphaMode) -> Self {
+        self.alpha_mode = alpha_mode;
+        self
+    }
+    
+    pub fn with_tint(mut self, tint: Tint) -> Self {
+        self.tint = Some(tint);
+        self
+    }
+    
+    // With an overlay, only the overlay is tinted and the base texture keeps its colors
+    pub fn with_overlay(mut self, overlay_texture_id: &str) -> Self {
+        self.overlay_texture_id = Some(overlay_texture_id.to_string());
+        self
+    }
+    
+    pub fn with_normal_map(mut self, normal_texture_id: &str, format: NormalMapFormat) -> Self {
+        self.normal_texture_id = Some(normal_texture_id.to_string());
+        self.normal_map_format = format;
+        self
+    }
+    
+    // A depth of 1/16 makes the height map span one pixel of a 16x16 block texture
+    pub fn with_height_map(mut self, height_texture_id: &str, depth: f32) -> Self {
+        self.height_texture_id = Some(height_texture_id.to_string());
+        self.parallax_depth = depth;
+        self
+    }
+    
+    // LabPBR textures from a resource pack; the `_n` alpha channel doubles as the height map
+    pub fn with_labpbr(mut self, normal_texture_id: Option<&str>, specular_texture_id: Option<&str>) -> Self {
+        if let Some(normal_texture_id) = normal_texture_id {
+            self = self.with_normal_map(normal_texture_id, NormalMapFormat::LabPbr);
+            self.parallax_depth = 0.25; // LabPBR height maps span a quarter of a block
+        }
+        self.specular_texture_id = specular_texture_id.map(str::to_string);
+        self
+    }
+    
+    // Must be called once the referenced textures are loaded; names that are not found sample as magenta
+    pub fn resolve_textures(&mut self, texture_manager: &TextureManager) {
+        let resolve = |id: &Option<String>| id.as_deref().map(|id| texture_manager.handle_or_missing(id));
+        let colormap = match &self.tint {
+            Some(Tint::ColorMap { texture_id, .. }) => Some(texture_id.clone()),
+            _ => None,
+        };
+        
+        self.textures = MaterialTextures {
+            base: resolve(&self.texture_id),
+            overlay: resolve(&self.overlay_texture_id),
+            emissive: resolve(&self.emissive_texture_id),
+            normal: resolve(&self.normal_texture_id),
+            height: resolve(&self.height_texture_id),
+            specular: resolve(&self.specular_texture_id),
+            metallic_roughness: resolve(&self.metallic_roughness_texture_id),
+            colormap: resolve(&colormap),
+        };
+    }
+    
+    pub fn base_color(&self, texture_manager: &TextureManager, coords: &TexCoords, footprint: f32) -> Color {
+        let tint = self.tint.as_ref().map(|tint| tint.color(texture_manager, self.textures.colormap));
+        let Some(texture) = self.textures.base else {
+            return tint.map_or(self.albedo, |tint| self.albedo.component_mul(&tint));
+        };
+        
+        let mut texture_color = texture_manager.sample_texture(texture, coords, footprint).0;
+        match (self.textures.overlay, tint) {
+            (Some(overlay), tint) => {
+                let (overlay_color, overlay_alpha) = texture_manager.sample_texture(overlay, coords, footprint);
+                let overlay_color = tint.map_or(overlay_color, |tint| overlay_color.component_mul(&tint));
+                texture_color = texture_color.lerp(&overlay_color, overlay_alpha);
+            }
+            (None, Some(tint)) => texture_color = texture_color.component_mul(&tint),
+            (None, None) => {}
+        }
+        self.albedo.component_mul(&texture_color)
+    }
+    
+    pub fn alpha(&self, texture_manager: &TextureManager, coords: &TexCoords) -> f32 {
+        match self.textures.base {
+            Some(texture) => texture_manager.sample_texture(texture, coords, 0.0).1,
+            None => 1.0,
+        }
+    }
+    
+    // Cutout texels are treated as if there was no surface at all
+    pub fn is_cut_out(&self, texture_manager: &TextureManager, coords: &TexCoords) -> bool {
+        match self.alpha_mode {
+            AlphaMode::Cutout(threshold) => self.alpha(texture_manager, coords) < threshold,
+            _ => false,
+        }
+    }
+    
+    pub fn with_refraction(mut self, transparency: f32, refractive_index: f32) -> Self {
         self.transparency = transparency;
-        self.reflectivity = reflectivity;
         self.refractive_index = refractive_index;
         self
     }
+    
+    pub fn with_metallic(mut self, metallic: f32) -> Self {
+        self.metallic = metallic;
+        self
+    }
+    
+    // Perceptual roughness; 0 gives mirror reflection and sharp refraction
+    pub fn with_roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+    
+    // Dielectrics reflect ((n - 1) / (n + 1))^2 head on: 0.02 for water, 0.04 for most solids
+    pub fn with_specular_f0(mut self, specular_f0: f32) -> Self {
+        self.specular_f0 = specular_f0;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureFilter {
+    Nearest,   // Single texel from the full resolution image
+    Bilinear,  // Bilinear lookup in the closest mip level
+    Trilinear, // Bilinear lookups in the two closest mip levels, blended
+}
+
+// An image with its mip chain, level 0 being the full resolution image
+pub struct MipChain {
+    pub levels: Vec<RgbaImage>,
+}
+
+impl MipChain {
+    pub fn new(image: RgbaImage) -> Self {
+        let mut levels = vec![image];
+        loop {
+            let previous = &levels[levels.len() - 1];
+            if previous.width() == 1 && previous.height() == 1 {
+                break;
+            }
+            let width = (previous.width() / 2).max(1);
+            let height = (previous.height() / 2).max(1);
+            levels.push(image::imageops::resize(previous, width, height, FilterType::Triangle));
+        }
+        Self { levels }
+    }
+    
+    fn texel(level: &RgbaImage, x: i64, y: i64) -> Vector4<f32> {
+        // Textures repeat outside the [0, 1] range
+        let x = x.rem_euclid(level.width() as i64) as u32;
+        let y = y.rem_euclid(level.height() as i64) as u32;
+        let pixel = level.get_pixel(x, y);
+        Vector4::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32, pixel[3] as f32) / 255.0
+    }
+    
+    fn sample_nearest(&self, level: usize, u: f32, v: f32) -> Vector4<f32> {
+        let image = &self.levels[level];
+        let x = (u * image.width() as f32).floor() as i64;
+        let y = (v * image.height() as f32).floor() as i64;
+        Self::texel(image, x, y)
+    }
+    
+    fn sample_bilinear(&self, level: usize, u: f32, v: f32) -> Vector4<f32> {
+        let image = &self.levels[level];
+        let x = u * image.width() as f32 - 0.5;
+        let y = v * image.height() as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        
+        let top = Self::texel(image, x0, y0).lerp(&Self::texel(image, x0 + 1, y0), tx);
+        let bottom = Self::texel(image, x0, y0 + 1).lerp(&Self::texel(image, x0 + 1, y0 + 1), tx);
+        top.lerp(&bottom, ty)
+    }
+    
+    // `footprint` is the size of the pixel footprint in texture coordinates
+    pub fn sample(&self, u: f32, v: f32, footprint: f32, filter: TextureFilter) -> Vector4<f32> {
+        let base = &self.levels[0];
+        let texels = footprint * base.width().max(base.height()) as f32;
+        let lod = if texels > 1.0 { texels.log2() } else { 0.0 };
+        let max_level = self.levels.len() - 1;
+        
+        // Magnified pixel art stays sharp, filtering only kicks in once texels get smaller than pixels
+        if filter == TextureFilter::Nearest || lod <= 0.0 {
+            return self.sample_nearest(0, u, v);
+        }
+        
+        match filter {
+            TextureFilter::Bilinear => {
+                self.sample_bilinear((lod.round() as usize).min(max_level), u, v)
+            }
+            _ => {
+                let lower = (lod.floor() as usize).min(max_level);
+                let upper = (lower + 1).min(max_level);
+                let blend = lod - lod.floor();
+                self.sample_bilinear(lower, u, v).lerp(&self.sample_bilinear(upper, u, v), blend)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationFrame {
+    pub index: usize,
+    pub ticks: u32, // Minecraft ticks, 20 per second
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub frames: Vec<AnimationFrame>,
+    pub interpolate: bool,
+}
+
+impl Animation {
+    pub const TICKS_PER_SECOND: f32 = 20.0;
+    
+    // Every strip frame in order, each shown for `ticks`
+    pub fn sequential(frame_count: usize, ticks: u32) -> Self {
+        Self {
+            frames: (0..frame_count).map(|index| AnimationFrame { index, ticks }).collect(),
+            interpolate: false,
+        }
+    }
+    
+    // Reads the "animation" section of a Minecraft .mcmeta file
+    pub fn from_mcmeta(json: &str, frame_count: usize) -> Result<Self, Box<dyn std::error::Error>> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        let section = value.get("animation").ok_or("mcmeta file has no animation section")?;
+        
+        let default_ticks = section.get("frametime").and_then(|t| t.as_u64()).unwrap_or(1).max(1) as u32;
+        let interpolate = section.get("interpolate").and_then(|i| i.as_bool()).unwrap_or(false);
+        
+        let frames = match section.get("frames").and_then(|f| f.as_array()) {
+            Some(frames) => frames.iter()
+                .filter_map(|frame| {
+                    // Frames are either a bare index or {"index": i, "time": t}
+                    let (index, ticks) = match frame.as_u64() {
+                        Some(index) => (index, default_ticks as u64),
+                        None => (
+                            frame.get("index")?.as_u64()?,
+                            frame.get("time").and_then(|t| t.as_u64()).unwrap_or(default_ticks as u64),
+                        ),
+                    };
+                    Some(AnimationFrame { index: index as usize, ticks: ticks.max(1) as u32 })
+                })
+                .filter(|frame| frame.index < frame_count)
+                .collect(),
+            None => Self::sequential(frame_count, default_ticks).frames,
+        };
+        
+        if frames.is_empty() {
+            return Err("mcmeta animation has no valid frames".into());
+        }
+        Ok(Self { frames, interpolate })
+    }
+    
+    // Strip frames to show at `time` seconds and the blend factor towards the second one
+    pub fn frames_at(&self, time: f32) -> (usize, usize, f32) {
+        let total_ticks: u32 = self.frames.iter().map(|frame| frame.ticks).sum();
+        let mut tick = (time.max(0.0) * Self::TICKS_PER_SECOND) % total_ticks as f32;
+        
+        for (position, frame) in self.frames.iter().enumerate() {
+            if tick < frame.ticks as f32 {
+                let next = &self.frames[(position + 1) % self.frames.len()];
+                let blend = if self.interpolate { tick / frame.ticks as f32 } else { 0.0 };
+                return (frame.index, next.index, blend);
+            }
+            tick -= frame.ticks as f32;
+        }
+        
+        let last = self.frames[self.frames.len() - 1].index;
+        (last, last, 0.0)
+    }
+}
+
+// Where a texture is looked up: the surface coordinates, and the world position for solid textures
+#[derive(Debug, Clone, Copy)]
+pub struct TexCoords {
+    pub u: f32,
+    pub v: f32,
+    pub point: Vec3,
+}
+
+impl TexCoords {
+    pub fn new(u: f32, v: f32, point: Vec3) -> Self {
+        Self { u, v, point }
+    }
+    
+    pub fn from_hit(hit: &HitRecord) -> Self {
+        Self::new(hit.u, hit.v, hit.point)
+    }
+    
+    pub fn with_uv(self, u: f32, v: f32) -> Self {
+        Self { u, v, ..self }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TextureQuery {
+    pub coords: TexCoords,
+    pub footprint: f32, // Size of the pixel footprint in texture coordinates
+    pub filter: TextureFilter,
+    pub time: f32,
+}
+
+// Anything a material can read colors from: images, or patterns computed on the fly
+pub trait Texture: Send + Sync {
+    // RGBA at the queried point
+    fn sample(&self, query: &TextureQuery) -> Vector4<f32>;
+}
+
+// A texture made of one or more frames; animated textures come from vertical strips
+pub struct ImageTexture {
+    pub frames: Vec<MipChain>,
+    pub animation: Option<Animation>,
+    pub data: bool, // Holds encoded values rather than colors, read texel by texel
+}
+
+impl ImageTexture {
+    pub fn new(image: RgbaImage) -> Self {
+        Self {
+            frames: vec![MipChain::new(image)],
+            animation: None,
+            data: false,
+        }
+    }
+    
+    pub fn animated(strip: RgbaImage, frame_height: u32, animation: Option<Animation>) -> Self {
+        let frame_count = (strip.height() / frame_height.max(1)).max(1);
+        let frames = (0..frame_count)
+            .map(|frame| {
+                let view = image::imageops::crop_imm(&strip, 0, frame * frame_height, strip.width(), frame_height);
+                MipChain::new(view.to_image())
+            })
+            .collect();
+        let animation = animation.unwrap_or_else(|| Animation::sequential(frame_count as usize, 1));
+        
+        Self { frames, animation: Some(animation), data: false }
+    }
+    
+    // Data maps like LabPBR `_n` and `_s` pack codes into their channels, which blending texels,
+    // mip levels or animation frames would turn into other codes
+    pub fn into_data(mut self) -> Self {
+        for frame in &mut self.frames {
+            frame.levels.truncate(1);
+        }
+        self.data = true;
+        self
+    }
 }
 
+impl Texture for ImageTexture {
+    fn sample(&self, query: &TextureQuery) -> Vector4<f32> {
+        let (u, v, footprint) = (query.coords.u, query.coords.v, query.footprint);
+        let filter = if self.data { TextureFilter::Nearest } else { query.filter };
+        match &self.animation {
+            Some(animation) => {
+                let (current, next, blend) = animation.frames_at(query.time);
+                let texel = self.frames[current].sample(u, v, footprint, filter);
+                if blend > 0.0 && !self.data {
+                    texel.lerp(&self.frames[next].sample(u, v, footprint, filter), blend)
+                } else {
+                    texel
+                }
+            }
+            None => self.frames[0].sample(u, v, footprint, filter),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureHandle(u32);
+
+impl TextureHandle {
+    // Stands in for textures that were referenced but never loaded
+    pub const MISSING: TextureHandle = TextureHandle(u32::MAX);
+}
+
+// Textures live in one array indexed by handle; names are only used while building the scene
 pub struct TextureManager {
-    textures: HashMap<String, RgbaImage>,
+    textures: Vec<Box<dyn Texture>>,
+    handles: HashMap<String, TextureHandle>,
+    pub filter: TextureFilter,
+    pub time: f32, // Scene time in seconds, selects the frame of animated textures
 }
 
 impl TextureManager {
     pub fn new() -> Self {
         Self {
-            textures: HashMap::new(),
+            textures: Vec::new(),
+            handles: HashMap::new(),
+            filter: TextureFilter::Trilinear,
+            time: 0.0,
         }
     }
     
-    pub fn load_texture(&mut self, id: &str, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let img = image::open(path)?;
-        let rgba_img = img.to_rgba8();
-        self.textures.insert(id.to_string(), rgba_img);
-        Ok(())
+    pub fn load_texture(&mut self, id: &str, path: &str) -> Result<TextureHandle, Box<dyn std::error::Error>> {
+        let bytes = std::fs::read(path)?;
+        
+        // Minecraft keeps animation metadata next to the image, e.g. water_still.png.mcmeta
+        let mcmeta_path = format!("{}.mcmeta", path);
+        let mcmeta = std::fs::read_to_string(&mcmeta_path).ok();
+        
+        self.load_texture_from_memory(id, &bytes, mcmeta.as_deref())
     }
     
-    pub fn sample_texture(&self, texture_id: &str, u: f32, v: f32) -> Color {
-        if let Some(texture) = self.textures.get(texture_id) {
-            let width = texture.width() as f32;
-            let height = texture.height() as f32;
-            
-            let x = ((u.fract() * width) as u32).min(texture.width() - 1);
-            let y = ((v.fract() * height) as u32).min(texture.height() - 1);
-            
-            let pixel = texture.get_pixel(x, y);
-            Color::new(
-                pixel[0] as f32 / 255.0,
-                pixel[1] as f32 / 255.0,
-                pixel[2] as f32 / 255.0,
-            )
+    // Same as `load_texture` for images that do not live in their own file, like resource pack zips
+    pub fn load_texture_from_memory(&mut self, id: &str, bytes: &[u8], mcmeta: Option<&str>) -> Result<TextureHandle, Box<dyn std::error::Error>> {
+        Ok(self.add_texture(id, Self::decode_texture(bytes, mcmeta)?))
+    }
+    
+    // Loads a data map, such as LabPBR normals or specular, to be read without filtering
+    pub fn load_data_texture_from_memory(&mut self, id: &str, bytes: &[u8], mcmeta: Option<&str>) -> Result<TextureHandle, Box<dyn std::error::Error>> {
+        Ok(self.add_texture(id, Self::decode_texture(bytes, mcmeta)?.into_data()))
+    }
+    
+    fn decode_texture(bytes: &[u8], mcmeta: Option<&str>) -> Result<ImageTexture, Box<dyn std::error::Error>> {
+        let rgba_img = image::load_from_memory(bytes)?.to_rgba8();
+        
+        // Animation strips stack square frames vertically
+        let frame_height = rgba_img.width();
+        let is_strip = rgba_img.height() > frame_height && rgba_img.height() % frame_height == 0;
+        
+        if mcmeta.is_some() || is_strip {
+            let frame_height = frame_height.min(rgba_img.height());
+            let frame_count = (rgba_img.height() / frame_height) as usize;
+            let animation = match mcmeta {
+                Some(json) => Some(Animation::from_mcmeta(json, frame_count)?),
+                None => None,
+            };
+            Ok(ImageTexture::animated(rgba_img, frame_height, animation))
+        } else {
+            Ok(ImageTexture::new(rgba_img))
+        }
+    }
+    
+    pub fn insert_texture(&mut self, id: &str, image: RgbaImage) -> TextureHandle {
+        self.add_texture(id, ImageTexture::new(image))
+    }
+    
+    // Registers any texture under an id, such as a procedural one.
+    // Reloading an id replaces the texture behind its existing handle.
+    pub fn add_texture(&mut self, id: &str, texture: impl Texture + 'static) -> TextureHandle {
+        let texture: Box<dyn Texture> = Box::new(texture);
+        if let Some(&handle) = self.handles.get(id) {
+            self.textures[handle.0 as usize] = texture;
+            return handle;
+        }
+        
+        let handle = TextureHandle(self.textures.len() as u32);
+        self.textures.push(texture);
+        self.handles.insert(id.to_string(), handle);
+        handle
+    }
+    
+    pub fn handle(&self, id: &str) -> Option<TextureHandle> {
+        self.handles.get(id).copied()
+    }
+    
+    pub fn handle_or_missing(&self, id: &str) -> TextureHandle {
+        self.handle(id).unwrap_or(TextureHandle::MISSING)
+    }
+    
+    pub fn sample_texture(&self, handle: TextureHandle, coords: &TexCoords, footprint: f32) -> (Color, f32) {
+        if let Some(texture) = self.textures.get(handle.0 as usize) {
+            let query = TextureQuery { coords: *coords, footprint, filter: self.filter, time: self.time };
+            let texel = texture.sample(&query);
+            (texel.xyz(), texel.w)
         } else {
-            Color::new(1.0, 0.0, 1.0) // Magenta for missing texture
+            (Color::new(1.0, 0.0, 1.0), 1.0) // Magenta for missing texture
         }
     }
 }
 
+// Single scattering albedo that makes a thick slab reflect `albedo` overall (Chiang et al. 2016)
+fn single_scattering_albedo(albedo: f32) -> f32 {
+    let albedo = albedo.clamp(0.0, 1.0);
+    let root = (9.59217 + 41.6808 * albedo + 17.7126 * albedo * albedo).sqrt();
+    (1.0 - (4.09712 + 4.20863 * albedo - root).powi(2)).clamp(0.0, 1.0)
+}
+
 pub fn create_materials() -> Vec<Material> {
     vec![
         // Grass block
         Material::new("grass")
             .with_texture("grass_side")
             .with_albedo(0.4, 0.8, 0.2)
-            .with_properties(0.1, 0.0, 0.05, 1.0),
+            .with_specular_f0(0.04),
             
         // Glass
         Material::new("glass")
             .with_texture("glass")
             .with_albedo(0.9, 0.9, 1.0)
-            .with_properties(0.9, 0.9, 0.1, 1.52),
+            .with_refraction(0.9, 1.52)
+            .with_specular_f0(0.04)
+            .with_roughness(0.0)
+            .with_absorption(0.05, 0.03, 0.05)
+            .with_dispersion(Dispersion::BK7_GLASS)
+            .with_alpha_mode(AlphaMode::Cutout(0.5)),
             
         // Iron
         Material::new("iron")
             .with_texture("iron_block")
             .with_albedo(0.7, 0.7, 0.8)
-            .with_properties(0.8, 0.0, 0.9, 1.0),
+            .with_metallic(1.0),
             
         // Diamond
         Material::new("diamond")
             .with_texture("diamond_block")
             .with_albedo(0.8, 0.9, 1.0)
-            .with_properties(0.95, 0.3, 0.8, 2.42),
+            .with_refraction(0.3, 2.42)
+            .with_specular_f0(0.17)
+            .with_roughness(0.0)
+            .with_dispersion(Dispersion::DIAMOND),
             
         // Water
         Material::new("water")
             .with_texture("water_still")
             .with_albedo(0.2, 0.4, 0.8)
-            .with_properties(0.7, 0.8, 0.3, 1.33),
+            .with_refraction(0.8, 1.33)
+            .with_specular_f0(0.02)
+            .with_roughness(0.0)
+            .with_absorption(0.9, 0.35, 0.12)
+            .with_scattering(0.04, 0.06, 0.08, 0.8)
+            .with_dispersion(Dispersion::WATER),
+            
+        // Grass block top, grayscale texture tinted with the plains grass color
+        Material::new("grass_top")
+            .with_texture("grass_top")
+            .with_albedo(1.0, 1.0, 1.0)
+            .with_tint(Tint::Fixed(Color::new(0.569, 0.741, 0.349)))
+            .with_specular_f0(0.04),
+            
+        // Dirt, bottom of grass blocks
+        Material::new("dirt")
+            .with_texture("dirt")
+            .with_albedo(1.0, 1.0, 1.0)
+            .with_specular_f0(0.04),
+            
+        // Leaves, glowing when the sun is behind them
+        Material::new("leaves")
+            .with_albedo(0.28, 0.48, 0.16)
+            .with_specular_f0(0.04)
+            .with_translucency(0.4),
+            
+        // Slime block, light scatters deep inside the jelly
+        Material::new("slime")
+            .with_albedo(0.45, 0.85, 0.35)
+            .with_refraction(1.0, 1.35)
+            .with_specular_f0(0.02)
+            .with_subsurface(0.6, 0.8, 0.5),
+            
+        // Snow, light scatters just below the surface
+        Material::new("snow")
+            .with_albedo(0.95, 0.97, 1.0)
+            .with_refraction(1.0, 1.31)
+            .with_specular_f0(0.02)
+            .with_subsurface(0.05, 0.06, 0.08),
+            
+        // Polished marble, veins from the procedural "marble" texture
+        Material::new("marble")
+            .with_texture("marble")
+            .with_albedo(1.0, 1.0, 1.0)
+            .with_specular_f0(0.05),
+            
+        // Stone with procedural speckles and variation across the world
+        Material::new("stone")
+            .with_texture("stone")
+            .with_albedo(1.0, 1.0, 1.0)
+            .with_specular_f0(0.04),
+            
+        // Ground colored by height through the procedural "terrain" texture
+        Material::new("terrain")
+            .with_texture("terrain")
+            .with_albedo(1.0, 1.0, 1.0)
+            .with_specular_f0(0.04),
+            
+        // Stone overgrown with moss from the procedural "mossy_stone" texture
+        Material::new("mossy_stone")
+            .with_texture("mossy_stone")
+            .with_albedo(1.0, 1.0, 1.0)
+            .with_specular_f0(0.04),
+            
+        // Polished checkered floor from the procedural "tiles" texture
+        Material::new("tiles")
+            .with_texture("tiles")
+            .with_albedo(1.0, 1.0, 1.0)
+            .with_specular_f0(0.05)
+            .with_roughness(0.2),
     ]
 }
 
 #[derive(Debug, Clone)]
 pub struct ScatterResult {
     pub scattered_ray: Ray,
-    pub attenuation: Color,
-    pub pdf: f32,
+    pub attenuation: Color, // BSDF times cosine over pdf
+    pub pdf: f32,           // Solid angle pdf of the scattered direction, 0 for mirror and glass
+    pub is_specular: bool,
 }
 
 impl Material {
-    pub fn scatter(&self, ray: &Ray, hit_point: Vec3, normal: Vec3, texture_manager: &TextureManager, u: f32, v: f32) -> Option<ScatterResult> {
-        let mut base_color = self.albedo;
+    pub fn emitted(&self, texture_manager: &TextureManager, coords: &TexCoords) -> Color {
+        let emission = if let Some(texture) = self.textures.emissive {
+            self.emission.component_mul(&texture_manager.sample_texture(texture, coords, 0.0).0)
+        } else {
+            self.emission
+        };
         
-        // Apply texture if available
-        if let Some(texture_id) = &self.texture_id {
-            let texture_color = texture_manager.sample_texture(texture_id, u, v);
-            base_color = base_color.component_mul(&texture_color);
+        // LabPBR emission makes the base color itself glow
+        if self.textures.specular.is_some() {
+            let strength = self.surface(texture_manager, coords, 0.0, &Color::zeros()).emission;
+            if strength > 0.0 {
+                return emission + self.base_color(texture_manager, coords, 0.0) * strength;
+            }
         }
+        emission
+    }
+    
+    // Surface properties at a texel. `base_color` is the reflectance of LabPBR "albedo" metals.
+    pub fn surface(&self, texture_manager: &TextureManager, coords: &TexCoords, footprint: f32, base_color: &Color) -> SurfaceSample {
+        let Some(specular_texture) = self.textures.specular else {
+            // glTF scales the roughness and metallic factors by the texture
+            let (roughness, metalness) = match self.textures.metallic_roughness {
+                Some(texture) => {
+                    let texel = texture_manager.sample_texture(texture, coords, footprint).0;
+                    (self.roughness * texel.y, self.metallic * texel.z)
+                }
+                None => (self.roughness, self.metallic),
+            };
+            return SurfaceSample {
+                roughness,
+                metalness,
+                f0: *base_color,
+                specular_f0: self.specular_f0,
+                porosity: 0.0,
+                subsurface: 0.0,
+                emission: 0.0,
+            };
+        };
         
-        let mut attenuation = base_color;
-        let incident = ray.direction;
-        let cos_i = -incident.dot(&normal);
+        // Channels are decoded from their 8-bit values as the LabPBR format defines them
+        let (texel, alpha) = texture_manager.sample_texture(specular_texture, coords, footprint);
+        let green = (texel.y * 255.0).round() as u32;
+        let blue = (texel.z * 255.0).round() as u32;
+        let alpha = (alpha * 255.0).round() as u32;
         
-        // Handle reflection and refraction
-        if self.transparency > 0.0 && self.refractive_index != 1.0 {
-            let eta = if cos_i > 0.0 { 1.0 / self.refractive_index } else { self.refractive_index };
-            let fresnel_factor = fresnel(cos_i.abs(), eta);
-            
-            use rand::Rng;
-            let mut rng = rand::thread_rng();
-            
-            if rng.r#gen::<f32>() < fresnel_factor * (1.0 - self.transparency) + self.reflectivity {
-                // Reflection
-                let reflected = reflect(incident, normal);
-                let scattered_direction = if self.roughness > 0.0 {
-                    (reflected + self.roughness * random_in_unit_sphere()).normalize()
-                } else {
-                    reflected
-                };
-                
-                Some(ScatterResult {
-                    scattered_ray: Ray::new(hit_point.into(), scattered_direction),
-                    attenuation,
-                    pdf: 1.0,
-                })
-            } else if let Some(refracted) = refract(incident, normal, eta) {
-                // Refraction
-                attenuation *= self.transparency;
-                Some(ScatterResult {
-                    scattered_ray: Ray::new(hit_point.into(), refracted),
-                    attenuation,
-                    pdf: 1.0,
-                })
-            } else {
-                // Total internal reflection
-                let reflected = reflect(incident, normal);
-                Some(ScatterResult {
-                    scattered_ray: Ray::new(hit_point.into(), reflected),
-                    attenuation,
-                    pdf: 1.0,
-                })
+        let (metalness, f0) = match green {
+            0..=229 => (0.0, *base_color),
+            230..=237 => (1.0, LABPBR_METALS[(green - 230) as usize]),
+            _ => (1.0, *base_color), // 238..=254 are reserved and treated like 255
+        };
+        let (porosity, subsurface) = if blue <= 64 {
+            (blue as f32 / 64.0, 0.0)
+        } else {
+            (0.0, (blue - 65) as f32 / 190.0)
+        };
+        
+        SurfaceSample {
+            roughness: 1.0 - texel.x,
+            metalness,
+            f0,
+            specular_f0: texel.y,
+            porosity,
+            subsurface,
+            emission: if alpha < 255 { alpha as f32 / 254.0 } else { 0.0 },
+        }
+    }
+    
+    // Steep parallax mapping: march the view ray through the height field in texture space
+    fn parallax_coords(&self, texture_manager: &TextureManager, ray: &Ray, hit: &HitRecord, footprint: f32) -> TexCoords {
+        const LAYERS: usize = 16;
+        let coords = TexCoords::from_hit(hit);
+        
+        // LabPBR keeps the height in the alpha channel of the normal map
+        let (height_texture, height_channel) = match (self.textures.height, self.textures.normal) {
+            (Some(height), _) => (height, 0),
+            (None, Some(normal)) if self.normal_map_format == NormalMapFormat::LabPbr => (normal, 3),
+            _ => return coords,
+        };
+        if self.parallax_depth <= 0.0 {
+            return coords;
+        }
+        
+        let view = -ray.direction.normalize();
+        let view_z = view.dot(&hit.normal);
+        if view_z <= EPSILON {
+            return coords;
+        }
+        
+        // Moving one layer down shifts the texture coordinates away from the viewer
+        let layer_depth = 1.0 / LAYERS as f32;
+        let scale = self.parallax_depth * layer_depth / view_z.max(0.1);
+        let du = -view.dot(&hit.tangent) * scale;
+        let dv = -view.dot(&hit.bitangent) * scale;
+        let surface_depth = |u: f32, v: f32| {
+            let (color, alpha) = texture_manager.sample_texture(height_texture, &coords.with_uv(u, v), footprint);
+            1.0 - if height_channel == 3 { alpha } else { color.x }
+        };
+        
+        let (mut u, mut v) = (hit.u, hit.v);
+        let mut depth = 0.0;
+        let mut previous_gap = surface_depth(u, v);
+        if previous_gap <= 0.0 {
+            return coords;
+        }
+        
+        for _ in 0..LAYERS {
+            u += du;
+            v += dv;
+            depth += layer_depth;
+            let gap = surface_depth(u, v) - depth;
+            if gap <= 0.0 {
+                // Interpolate between the last two layers to hide the stepping
+                let t = previous_gap / (previous_gap - gap);
+                return coords.with_uv(u - du * (1.0 - t), v - dv * (1.0 - t));
             }
-        } else if self.reflectivity > 0.0 {
-            // Pure reflection
-            let reflected = reflect(incident, normal);
-            let scattered_direction = if self.roughness > 0.0 {
-                (reflected + self.roughness * random_in_unit_sphere()).normalize()
+            previous_gap = gap;
+        }
+        coords.with_uv(u, v)
+    }
+    
+    // Normal used for shading, from the normal map in the face's tangent frame when there is one
+    fn shading_normal(&self, texture_manager: &TextureManager, ray: &Ray, hit: &HitRecord, coords: &TexCoords, footprint: f32) -> Vec3 {
+        let Some(normal_texture) = self.textures.normal else {
+            return hit.normal;
+        };
+        
+        let texel = texture_manager.sample_texture(normal_texture, coords, footprint).0;
+        let x = texel.x * 2.0 - 1.0;
+        let y = match self.normal_map_format {
+            // Texture v grows down the image, so an OpenGL "up" is -v
+            NormalMapFormat::OpenGl => 1.0 - texel.y * 2.0,
+            NormalMapFormat::LabPbr => texel.y * 2.0 - 1.0,
+        };
+        // Only x and y are trusted, LabPBR stores ambient occlusion in the blue channel
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+        
+        // The frame is built around the outward normal so back faces keep the same handedness
+        let outward = if hit.front_face { hit.normal } else { -hit.normal };
+        let tangent = (hit.tangent - outward * outward.dot(&hit.tangent)).try_normalize(EPSILON);
+        let bitangent = (hit.bitangent - outward * outward.dot(&hit.bitangent)).try_normalize(EPSILON);
+        let (Some(tangent), Some(bitangent)) = (tangent, bitangent) else {
+            return hit.normal;
+        };
+        
+        let Some(mut normal) = (tangent * x + bitangent * y + outward * z).try_normalize(EPSILON) else {
+            return hit.normal;
+        };
+        if !hit.front_face {
+            normal = -normal;
+        }
+        
+        // A normal facing away from the viewer would send rays into the surface
+        if normal.dot(&ray.direction) >= 0.0 {
+            hit.normal
+        } else {
+            normal
+        }
+    }
+    
+    // The BSDF at a hit: a clear coated diffuse base blended with metal by metalness,
+    // and with a refracting dielectric by transparency.
+    // With wavelengths, colors become radiance values at those wavelengths.
+    pub fn bsdf(&self, ray: &Ray, hit: &HitRecord, texture_manager: &TextureManager, footprint: f32, wavelengths: Option<&Wavelengths>) -> SurfaceBsdf {
+        let spectrum = |rgb: Color| wavelengths.map_or(rgb, |wavelengths| wavelengths.upsample(&rgb));
+        let coords = self.parallax_coords(texture_manager, ray, hit, footprint);
+        let normal = self.shading_normal(texture_manager, ray, hit, &coords, footprint);
+        let rgb_base_color = self.base_color(texture_manager, &coords, footprint);
+        let surface = self.surface(texture_manager, &coords, footprint, &rgb_base_color);
+        
+        // Water soaks into porous texels, darkening them, and pools as a smooth film on the rest
+        let soaked = self.wetness * surface.porosity;
+        let pooled = self.wetness * (1.0 - surface.porosity);
+        let base_color = spectrum(rgb_base_color * (1.0 - 0.5 * soaked));
+        let distribution = Ggx::from_roughness(surface.roughness * (1.0 - 0.8 * pooled));
+        
+        // Without a coat the base is a plain diffuse surface
+        let base: Box<dyn Bsdf> = if surface.specular_f0 > 0.0 {
+            Box::new(Plastic { diffuse: base_color, f0: surface.specular_f0, distribution })
+        } else {
+            Box::new(Lambertian { albedo: base_color })
+        };
+        let metal = Box::new(Conductor { f0: spectrum(surface.f0), distribution });
+        let mut bsdf = Mix::boxed(base, metal, surface.metalness);
+        
+        // LabPBR subsurface lets light through the same way, as volumes cannot vary per texel
+        let translucency = self.translucency.max(surface.subsurface);
+        if translucency > 0.0 {
+            let translucent = Box::new(Translucent { transmittance: base_color });
+            bsdf = Mix::boxed(bsdf, translucent, translucency * (1.0 - surface.metalness));
+        }
+        
+        if self.has_volume() {
+            // Dispersive materials refract every wavelength by its own index; the hero's is used
+            let ior = match (self.dispersion, wavelengths) {
+                (Some(dispersion), Some(wavelengths)) => dispersion.ior(wavelengths.hero()),
+                _ => self.refractive_index,
+            };
+            let eta = if hit.front_face { 1.0 / ior } else { ior };
+            // Materials with an interior get their color from the distance travelled inside
+            let interior = self.interior();
+            let transmittance = if interior.absorption == Color::zeros() && interior.scattering == Color::zeros() {
+                base_color
             } else {
-                reflected
+                Color::repeat(1.0)
             };
-            
-            attenuation *= self.reflectivity;
-            Some(ScatterResult {
-                scattered_ray: Ray::new(hit_point.into(), scattered_direction),
-                attenuation,
-                pdf: 1.0,
-            })
-        } else {
-            // Diffuse scattering
-            let scattered_direction = (normal + random_in_unit_sphere()).normalize();
-            Some(ScatterResult {
-                scattered_ray: Ray::new(hit_point.into(), scattered_direction),
-                attenuation,
-                pdf: 1.0,
-            })
+            let dielectric = Box::new(Dielectric { eta, distribution, transmittance });
+            bsdf = Mix::boxed(bsdf, dielectric, self.transparency);
+        }
+        
+        SurfaceBsdf {
+            frame: Frame::new(normal, hit.tangent),
+            geometric_normal: hit.normal,
+            bsdf,
         }
     }
 }
@@ -54,20 +54,31 @@ impl Ray {
     }
 }
 
-pub fn random_in_unit_sphere() -> Vec3 {
-    use rand::Rng;
-    let mut rng = rand::thread_rng();
+pub fn ray_hits_aabb(ray: &Ray, min: &Point3f, max: &Point3f, t_min: f32, t_max: f32) -> bool {
+    ray_aabb_interval(ray, min, max, t_min, t_max).is_some()
+}
+
+// Range of distances along the ray, clipped to [t_min, t_max], that lies inside the box
+pub fn ray_aabb_interval(ray: &Ray, min: &Point3f, max: &Point3f, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+    let mut t_near = t_min;
+    let mut t_far = t_max;
     
-    loop {
-        let p = Vec3::new(
-            rng.r#gen_range(-1.0..1.0),
-            rng.r#gen_range(-1.0..1.0),
-            rng.r#gen_range(-1.0..1.0),
-        );
-        if p.magnitude_squared() < 1.0 {
-            return p;
+    for axis in 0..3 {
+        let inv_dir = 1.0 / ray.direction[axis];
+        let mut t0 = (min[axis] - ray.origin[axis]) * inv_dir;
+        let mut t1 = (max[axis] - ray.origin[axis]) * inv_dir;
+        if inv_dir < 0.0 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+        
+        t_near = t_near.max(t0);
+        t_far = t_far.min(t1);
+        if t_near > t_far {
+            return None;
         }
     }
+    
+    Some((t_near, t_far))
 }
 
 pub trait Lerp {
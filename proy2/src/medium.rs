@@ -0,0 +1,180 @@
+use crate::math_utils::{Vec3, Point3f, Color, Ray, ray_aabb_interval};
+use std::f32::consts::PI;
+
+// Distance rays leaving the scene travel through unbounded fog before reaching the sky
+pub const FOG_HORIZON: f32 = 256.0;
+
+// Henyey-Greenstein phase function; g > 0 scatters forward, g < 0 backward
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HenyeyGreenstein {
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    // `cos_theta` is between the propagation direction and the scattered direction
+    pub fn eval(&self, cos_theta: f32) -> f32 {
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.max(1e-8).sqrt())
+    }
+
+    // Scattered direction for light travelling along `direction`, with its pdf
+    pub fn sample(&self, direction: &Vec3, u: (f32, f32)) -> (Vec3, f32) {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u.0
+        } else {
+            let s = (1.0 - g * g) / (1.0 + g - 2.0 * g * u.0);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+
+        let helper = if direction.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let tangent = helper.cross(direction).normalize();
+        let bitangent = direction.cross(&tangent);
+        let scattered = tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + direction * cos_theta;
+
+        (scattered, self.eval(cos_theta))
+    }
+}
+
+// Medium with the same density everywhere; coefficients are per block travelled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HomogeneousMedium {
+    pub absorption: Color,
+    pub scattering: Color,
+    pub phase: HenyeyGreenstein,
+}
+
+impl HomogeneousMedium {
+    pub fn new(absorption: Color, scattering: Color, g: f32) -> Self {
+        Self { absorption, scattering, phase: HenyeyGreenstein { g } }
+    }
+}
+
+// Fog or haze filling a box, or the whole world when it has no bounds
+#[derive(Debug, Clone)]
+pub struct FogVolume {
+    pub bounds: Option<(Point3f, Point3f)>,
+    pub medium: HomogeneousMedium,
+}
+
+impl FogVolume {
+    pub fn global(medium: HomogeneousMedium) -> Self {
+        Self { bounds: None, medium }
+    }
+
+    pub fn region(min: Point3f, max: Point3f, medium: HomogeneousMedium) -> Self {
+        Self { bounds: Some((min, max)), medium }
+    }
+
+    // Part of the ray between `t_min` and `t_max` inside the volume
+    pub fn interval(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        match &self.bounds {
+            Some((min, max)) => ray_aabb_interval(ray, min, max, t_min, t_max),
+            None => Some((t_min, t_max)),
+        }
+    }
+}
+
+// Stretch of a ray with constant coefficients; overlapping volumes add up
+#[derive(Debug, Clone, Copy)]
+pub struct MediumSegment {
+    pub start: f32,
+    pub end: f32,
+    pub absorption: Color,
+    pub scattering: Color,
+    pub phase: HenyeyGreenstein,
+}
+
+// Outcome of distance sampling along a ray
+pub enum MediumEvent {
+    // Scattered at distance `t`; `weight` is scattering times transmittance over the pdf
+    Scatter { t: f32, weight: Color, phase: HenyeyGreenstein },
+    // Reached the end of the ray; `weight` is transmittance over the pdf
+    Pass { weight: Color },
+}
+
+// Splits the ray into segments of constant media, skipping stretches without any.
+// Each medium comes with the interval of the ray it covers.
+pub fn segments(media: &[(HomogeneousMedium, f32, f32)], t_max: f32) -> Vec<MediumSegment> {
+    let mut breakpoints: Vec<f32> = media.iter()
+        .flat_map(|&(_, entry, exit)| [entry, exit])
+        .chain([0.0, t_max])
+        .filter(|t| (0.0..=t_max).contains(t))
+        .collect();
+    breakpoints.sort_by(|a, b| a.total_cmp(b));
+    breakpoints.dedup();
+
+    let mut segments = Vec::new();
+    for pair in breakpoints.windows(2) {
+        let (start, end) = (pair[0], pair[1]);
+        let middle = 0.5 * (start + end);
+        let mut absorption = Color::zeros();
+        let mut scattering = Color::zeros();
+        let mut weighted_g = 0.0;
+        for (medium, entry, exit) in media {
+            if (*entry..=*exit).contains(&middle) {
+                absorption += medium.absorption;
+                scattering += medium.scattering;
+                weighted_g += medium.phase.g * medium.scattering.mean();
+            }
+        }
+        if absorption == Color::zeros() && scattering == Color::zeros() {
+            continue;
+        }
+        // Overlapping volumes share one phase function, weighted by how much each scatters
+        let g = if scattering.mean() > 0.0 { weighted_g / scattering.mean() } else { 0.0 };
+        segments.push(MediumSegment { start, end, absorption, scattering, phase: HenyeyGreenstein { g } });
+    }
+    segments
+}
+
+// Transmittance through all segments
+pub fn transmittance(segments: &[MediumSegment]) -> Color {
+    let optical_depth: Color = segments.iter()
+        .map(|segment| (segment.absorption + segment.scattering) * (segment.end - segment.start))
+        .sum();
+    (-optical_depth).map(f32::exp)
+}
+
+// Picks where the ray first scatters. Distances are sampled by the scattering coefficient of
+// a random channel, so absorption only ever darkens the weight and media that do not scatter
+// reduce to Beer-Lambert transmittance.
+pub fn sample_distance(segments: &[MediumSegment], channel_u: f32, u: f32) -> MediumEvent {
+    let channel = ((channel_u * 3.0) as usize).min(2);
+    let mut target = -(1.0 - u).max(f32::MIN_POSITIVE).ln();
+
+    let mut extinction_depth = Color::zeros(); // Optical depth of absorption and scattering
+    let mut scattering_depth = Color::zeros(); // Optical depth of scattering alone
+    for segment in segments {
+        let length = segment.end - segment.start;
+        let sigma_s = segment.scattering[channel];
+        let sigma_t = segment.absorption + segment.scattering;
+
+        if sigma_s > 0.0 && target < sigma_s * length {
+            let distance = target / sigma_s;
+            let transmittance = (-(extinction_depth + sigma_t * distance)).map(f32::exp);
+            let scattering_transmittance = (-(scattering_depth + segment.scattering * distance)).map(f32::exp);
+            // Average pdf over the channels that could have been picked
+            let pdf = segment.scattering.component_mul(&scattering_transmittance).mean();
+            if pdf <= 0.0 {
+                break;
+            }
+            return MediumEvent::Scatter {
+                t: segment.start + distance,
+                weight: segment.scattering.component_mul(&transmittance) / pdf,
+                phase: segment.phase,
+            };
+        }
+
+        target -= sigma_s * length;
+        extinction_depth += sigma_t * length;
+        scattering_depth += segment.scattering * length;
+    }
+
+    let transmittance = (-extinction_depth).map(f32::exp);
+    let pass_probability = (-scattering_depth).map(f32::exp).mean();
+    MediumEvent::Pass { weight: transmittance / pass_probability.max(1e-8) }
+}
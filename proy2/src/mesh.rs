@@ -0,0 +1,145 @@
+use crate::math_utils::{Vec3, Point3f, Ray, EPSILON, ray_hits_aabb};
+use crate::cube::HitRecord;
+use nalgebra::Point3;
+
+#[derive(Debug, Clone)]
+pub struct Vertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub u: f32,
+    pub v: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    pub vertices: [Vertex; 3],
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+}
+
+impl Triangle {
+    pub fn new(a: Vertex, b: Vertex, c: Vertex) -> Self {
+        // Tangent frame from how the texture coordinates change across the triangle
+        let edge1 = b.position - a.position;
+        let edge2 = c.position - a.position;
+        let (du1, dv1) = (b.u - a.u, b.v - a.v);
+        let (du2, dv2) = (c.u - a.u, c.v - a.v);
+        let determinant = du1 * dv2 - du2 * dv1;
+
+        let (tangent, bitangent) = if determinant.abs() > EPSILON {
+            let r = 1.0 / determinant;
+            ((edge1 * dv2 - edge2 * dv1) * r, (edge2 * du1 - edge1 * du2) * r)
+        } else {
+            // No usable texture coordinates, any frame around the normal will do
+            let normal = edge1.cross(&edge2);
+            let helper = if normal.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+            let tangent = helper.cross(&normal);
+            (tangent, normal.cross(&tangent))
+        };
+
+        Self {
+            vertices: [a, b, c],
+            tangent: tangent.try_normalize(EPSILON).unwrap_or_else(Vec3::zeros),
+            bitangent: bitangent.try_normalize(EPSILON).unwrap_or_else(Vec3::zeros),
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, material_index: usize) -> Option<HitRecord> {
+        // Möller–Trumbore intersection
+        let [a, b, c] = &self.vertices;
+        let edge1 = b.position - a.position;
+        let edge2 = c.position - a.position;
+
+        let p = ray.direction.cross(&edge2);
+        let det = edge1.dot(&p);
+        if det.abs() < EPSILON {
+            return None; // Ray is parallel to the triangle
+        }
+
+        let inv_det = 1.0 / det;
+        let s = ray.origin.coords - a.position;
+        let w1 = s.dot(&p) * inv_det;
+        if !(0.0..=1.0).contains(&w1) {
+            return None;
+        }
+
+        let q = s.cross(&edge1);
+        let w2 = ray.direction.dot(&q) * inv_det;
+        if w2 < 0.0 || w1 + w2 > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(&q) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        // Interpolate shading attributes with the barycentric weights
+        let w0 = 1.0 - w1 - w2;
+        let mut normal = a.normal * w0 + b.normal * w1 + c.normal * w2;
+        if normal.magnitude_squared() < EPSILON {
+            normal = edge1.cross(&edge2);
+        }
+        let u = a.u * w0 + b.u * w1 + c.u * w2;
+        let v = a.v * w0 + b.v * w1 + c.v * w2;
+
+        let mut hit_record = HitRecord {
+            point: ray.at(t).coords,
+            normal: Vec3::zeros(),
+            tangent: self.tangent,
+            bitangent: self.bitangent,
+            t,
+            u,
+            v,
+            material_index,
+            front_face: false,
+        };
+
+        hit_record.set_face_normal(ray, normal.normalize());
+        Some(hit_record)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    pub triangles: Vec<Triangle>,
+    pub material_index: usize,
+    pub bounds_min: Point3f,
+    pub bounds_max: Point3f,
+}
+
+impl Mesh {
+    pub fn new(triangles: Vec<Triangle>, material_index: usize) -> Self {
+        let mut bounds_min = Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut bounds_max = Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+
+        for triangle in &triangles {
+            for vertex in &triangle.vertices {
+                for axis in 0..3 {
+                    bounds_min[axis] = bounds_min[axis].min(vertex.position[axis]);
+                    bounds_max[axis] = bounds_max[axis].max(vertex.position[axis]);
+                }
+            }
+        }
+
+        Self { triangles, material_index, bounds_min, bounds_max }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if !ray_hits_aabb(ray, &self.bounds_min, &self.bounds_max, t_min, t_max) {
+            return None;
+        }
+
+        let mut closest_hit: Option<HitRecord> = None;
+        let mut closest_t = t_max;
+
+        for triangle in &self.triangles {
+            if let Some(hit) = triangle.hit(ray, t_min, closest_t, self.material_index) {
+                closest_t = hit.t;
+                closest_hit = Some(hit);
+            }
+        }
+
+        closest_hit
+    }
+}
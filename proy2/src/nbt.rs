@@ -0,0 +1,194 @@
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::io::Read;
+
+type NbtResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+// Lists and compounds nested deeper than this are rejected, as Minecraft does, before the
+// recursion could overflow the stack
+const MAX_DEPTH: u32 = 512;
+
+#[derive(Debug, Clone)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    Skipped, // Floats, doubles and int and long arrays, which no loader reads
+}
+
+impl Tag {
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(entries) => entries.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Tag::Byte(value) => Some(*value as i64),
+            Tag::Short(value) => Some(*value as i64),
+            Tag::Int(value) => Some(*value as i64),
+            Tag::Long(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&HashMap<String, Tag>> {
+        match self {
+            Tag::Compound(entries) => Some(entries),
+            _ => None,
+        }
+    }
+}
+
+// Reads an NBT file, gzip-compressed or not, and returns the root tag and its name
+pub fn read_file(path: &str) -> NbtResult<(String, Tag)> {
+    let raw = std::fs::read(path)?;
+    let data = if raw.starts_with(&[0x1f, 0x8b]) {
+        let mut decoded = Vec::new();
+        GzDecoder::new(&raw[..]).read_to_end(&mut decoded)?;
+        decoded
+    } else {
+        raw
+    };
+
+    let mut reader = NbtReader { data: &data, position: 0, depth: 0 };
+    let tag_type = reader.u8()?;
+    if tag_type != 10 {
+        return Err("NBT root is not a compound tag".into());
+    }
+    let name = reader.string()?;
+    let root = reader.payload(tag_type)?;
+    Ok((name, root))
+}
+
+struct NbtReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    depth: u32, // Lists and compounds currently open
+}
+
+impl<'a> NbtReader<'a> {
+    fn bytes<const N: usize>(&mut self) -> NbtResult<[u8; N]> {
+        let end = self.position + N;
+        let bytes = self.data.get(self.position..end).ok_or("unexpected end of NBT data")?;
+        self.position = end;
+        Ok(bytes.try_into()?)
+    }
+
+    fn skip(&mut self, count: usize) -> NbtResult<()> {
+        let end = self.position.saturating_add(count);
+        if end > self.data.len() {
+            return Err("unexpected end of NBT data".into());
+        }
+        self.position = end;
+        Ok(())
+    }
+
+    fn u8(&mut self) -> NbtResult<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn length(&mut self) -> NbtResult<usize> {
+        let length = i32::from_be_bytes(self.bytes()?);
+        Ok(length.max(0) as usize)
+    }
+
+    fn string(&mut self) -> NbtResult<String> {
+        let length = u16::from_be_bytes(self.bytes()?) as usize;
+        let end = self.position + length;
+        let bytes = self.data.get(self.position..end).ok_or("unexpected end of NBT data")?;
+        self.position = end;
+        // Modified UTF-8 only differs from UTF-8 for NUL and supplementary characters
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    // Reads the contents of a list or compound, one level deeper
+    fn nested(&mut self, read: impl FnOnce(&mut Self) -> NbtResult<Tag>) -> NbtResult<Tag> {
+        if self.depth >= MAX_DEPTH {
+            return Err("NBT data nested too deeply".into());
+        }
+        self.depth += 1;
+        let tag = read(self);
+        self.depth -= 1;
+        tag
+    }
+
+    fn payload(&mut self, tag_type: u8) -> NbtResult<Tag> {
+        Ok(match tag_type {
+            1 => Tag::Byte(i8::from_be_bytes(self.bytes()?)),
+            2 => Tag::Short(i16::from_be_bytes(self.bytes()?)),
+            3 => Tag::Int(i32::from_be_bytes(self.bytes()?)),
+            4 => Tag::Long(i64::from_be_bytes(self.bytes()?)),
+            5 => {
+                self.skip(4)?;
+                Tag::Skipped
+            }
+            6 => {
+                self.skip(8)?;
+                Tag::Skipped
+            }
+            7 => {
+                let length = self.length()?;
+                let mut values = Vec::with_capacity(length.min(self.data.len()));
+                for _ in 0..length {
+                    values.push(i8::from_be_bytes(self.bytes()?));
+                }
+                Tag::ByteArray(values)
+            }
+            8 => Tag::String(self.string()?),
+            9 => self.nested(|reader| {
+                let element_type = reader.u8()?;
+                let length = reader.length()?;
+                let mut values = Vec::with_capacity(length.min(reader.data.len()));
+                for _ in 0..length {
+                    values.push(reader.payload(element_type)?);
+                }
+                Ok(Tag::List(values))
+            })?,
+            10 => self.nested(|reader| {
+                let mut entries = HashMap::new();
+                loop {
+                    let entry_type = reader.u8()?;
+                    if entry_type == 0 {
+                        break;
+                    }
+                    let name = reader.string()?;
+                    entries.insert(name, reader.payload(entry_type)?);
+                }
+                Ok(Tag::Compound(entries))
+            })?,
+            11 => {
+                let length = self.length()?;
+                self.skip(length.saturating_mul(4))?;
+                Tag::Skipped
+            }
+            12 => {
+                let length = self.length()?;
+                self.skip(length.saturating_mul(8))?;
+                Tag::Skipped
+            }
+            other => return Err(format!("unknown NBT tag type {}", other).into()),
+        })
+    }
+}
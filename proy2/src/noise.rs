@@ -0,0 +1,153 @@
+use crate::math_utils::Vec3;
+
+// Integer lattice hash; the same cell always gives the same value
+pub fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xcb1a_b31f)
+        ^ (z as u32).wrapping_mul(0xd816_3841);
+    h = (h ^ (h >> 13)).wrapping_mul(0x85eb_ca6b);
+    h = (h ^ (h >> 16)).wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+// Hash mapped to [0, 1]
+pub fn hash_float(x: i32, y: i32, z: i32) -> f32 {
+    hash(x, y, z) as f32 / u32::MAX as f32
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn floor_cell(p: &Vec3) -> (i32, i32, i32) {
+    (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32)
+}
+
+// Trilinear blend of values at the eight corners of the cell containing `p`
+fn interpolate_cell(p: &Vec3, corner: impl Fn(i32, i32, i32, Vec3) -> f32) -> f32 {
+    let (x, y, z) = floor_cell(p);
+    let f = p - Vec3::new(x as f32, y as f32, z as f32);
+    let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+    let value = |dx: i32, dy: i32, dz: i32| {
+        corner(x + dx, y + dy, z + dz, f - Vec3::new(dx as f32, dy as f32, dz as f32))
+    };
+
+    let bottom = lerp(lerp(value(0, 0, 0), value(1, 0, 0), u), lerp(value(0, 1, 0), value(1, 1, 0), u), v);
+    let top = lerp(lerp(value(0, 0, 1), value(1, 0, 1), u), lerp(value(0, 1, 1), value(1, 1, 1), u), v);
+    lerp(bottom, top, w)
+}
+
+// Smoothly interpolated random values on the integer lattice, in [0, 1]
+pub fn value(p: &Vec3) -> f32 {
+    interpolate_cell(p, |x, y, z, _| hash_float(x, y, z))
+}
+
+// The twelve cube edge directions of improved Perlin noise
+fn gradient(hash: u32, offset: &Vec3) -> f32 {
+    let (x, y, z) = (offset.x, offset.y, offset.z);
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+// Improved Perlin gradient noise, roughly in [-1, 1] and zero on the lattice
+pub fn perlin(p: &Vec3) -> f32 {
+    interpolate_cell(p, |x, y, z, offset| gradient(hash(x, y, z), &offset))
+}
+
+// Simplex noise in three dimensions (after Gustavson), roughly in [-1, 1]
+pub fn simplex(p: &Vec3) -> f32 {
+    const F3: f32 = 1.0 / 3.0;
+    const G3: f32 = 1.0 / 6.0;
+
+    // Skew into the grid of tetrahedra and find the corner the point is next to
+    let skew = (p.x + p.y + p.z) * F3;
+    let cell = (p + Vec3::repeat(skew)).map(f32::floor);
+    let unskew = (cell.x + cell.y + cell.z) * G3;
+    let x0 = p - (cell - Vec3::repeat(unskew));
+
+    // The order of the offsets picks which of the six tetrahedra the point lies in
+    let (first, second) = if x0.x >= x0.y {
+        if x0.y >= x0.z {
+            (Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0))
+        } else if x0.x >= x0.z {
+            (Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 1.0))
+        } else {
+            (Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 1.0))
+        }
+    } else if x0.y < x0.z {
+        (Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 1.0))
+    } else if x0.x < x0.z {
+        (Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 1.0))
+    } else {
+        (Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 1.0, 0.0))
+    };
+
+    let corners = [Vec3::zeros(), first, second, Vec3::repeat(1.0)];
+    let total: f32 = corners.iter()
+        .enumerate()
+        .map(|(i, corner)| {
+            let offset = x0 - corner + Vec3::repeat(i as f32 * G3);
+            let falloff = 0.6 - offset.norm_squared();
+            if falloff <= 0.0 {
+                return 0.0;
+            }
+            let lattice = cell + corner;
+            let h = hash(lattice.x as i32, lattice.y as i32, lattice.z as i32);
+            falloff.powi(4) * gradient(h, &offset)
+        })
+        .sum();
+    32.0 * total
+}
+
+// Worley (cellular) noise: distance to the closest of one random point per cell
+pub fn worley(p: &Vec3) -> f32 {
+    let (x, y, z) = floor_cell(p);
+    let mut closest = f32::MAX;
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (cx, cy, cz) = (x + dx, y + dy, z + dz);
+                let h = hash(cx, cy, cz);
+                let feature = Vec3::new(
+                    cx as f32 + hash_float(h as i32, 1, 0),
+                    cy as f32 + hash_float(h as i32, 2, 0),
+                    cz as f32 + hash_float(h as i32, 3, 0),
+                );
+                closest = closest.min((feature - p).norm_squared());
+            }
+        }
+    }
+    closest.sqrt()
+}
+
+// Fractal sum of octaves, each at twice the frequency and half the amplitude of the last.
+// Normalized so the result keeps the range of a single octave.
+pub fn fbm(p: &Vec3, octaves: u32, noise: impl Fn(&Vec3) -> f32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut total_amplitude = 0.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves.max(1) {
+        sum += noise(&(p * frequency)) * amplitude;
+        total_amplitude += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total_amplitude
+}
@@ -0,0 +1,129 @@
+use crate::math_utils::Vec3;
+use crate::materials::{Material, TextureManager, NormalMapFormat};
+use crate::mesh::{Mesh, Triangle, Vertex};
+use std::path::Path;
+
+pub fn load_obj(
+    path: &str,
+    offset: Vec3,
+    scale: f32,
+    materials: &mut Vec<Material>,
+    texture_manager: &mut TextureManager,
+) -> Result<Vec<Mesh>, Box<dyn std::error::Error>> {
+    let options = tobj::LoadOptions {
+        triangulate: true,
+        single_index: true,
+        ..Default::default()
+    };
+    let (models, obj_materials) = tobj::load_obj(path, &options)?;
+    let base_dir = Path::new(path).parent().unwrap_or(Path::new("."));
+
+    // MTL materials are appended after the ones already in the scene
+    let material_offset = materials.len();
+    let obj_materials = obj_materials.unwrap_or_default();
+    for obj_material in &obj_materials {
+        materials.push(convert_material(obj_material, base_dir, texture_manager)?);
+    }
+
+    // Models without a material fall back to a plain grey one
+    let mut default_material = None;
+
+    let mut meshes = Vec::new();
+    for model in &models {
+        let mesh = &model.mesh;
+        let material_index = match mesh.material_id {
+            Some(id) if id < obj_materials.len() => material_offset + id,
+            _ => *default_material.get_or_insert_with(|| {
+                materials.push(Material::new("obj_default"));
+                materials.len() - 1
+            }),
+        };
+
+        let vertex = |index: u32| -> Vertex {
+            let i = index as usize;
+            let position = Vec3::new(
+                mesh.positions[3 * i],
+                mesh.positions[3 * i + 1],
+                mesh.positions[3 * i + 2],
+            ) * scale + offset;
+            let normal = if mesh.normals.is_empty() {
+                Vec3::zeros()
+            } else {
+                Vec3::new(mesh.normals[3 * i], mesh.normals[3 * i + 1], mesh.normals[3 * i + 2])
+            };
+            let (u, v) = if mesh.texcoords.is_empty() {
+                (0.0, 0.0)
+            } else {
+                // OBJ texture coordinates have V pointing up, images have it pointing down
+                (mesh.texcoords[2 * i], 1.0 - mesh.texcoords[2 * i + 1])
+            };
+            Vertex { position, normal, u, v }
+        };
+
+        let triangles: Vec<Triangle> = mesh.indices
+            .chunks_exact(3)
+            .map(|face| Triangle::new(vertex(face[0]), vertex(face[1]), vertex(face[2])))
+            .collect();
+
+        if !triangles.is_empty() {
+            meshes.push(Mesh::new(triangles, material_index));
+        }
+    }
+
+    Ok(meshes)
+}
+
+fn convert_material(
+    obj_material: &tobj::Material,
+    base_dir: &Path,
+    texture_manager: &mut TextureManager,
+) -> Result<Material, Box<dyn std::error::Error>> {
+    let mut material = Material::new(&obj_material.name);
+
+    if let Some([r, g, b]) = obj_material.diffuse {
+        material = material.with_albedo(r, g, b);
+    }
+
+    if let Some(texture) = &obj_material.diffuse_texture {
+        let texture_path = base_dir.join(texture);
+        let texture_id = texture_path.to_string_lossy().to_string();
+        texture_manager.load_texture(&texture_id, &texture_id)?;
+        material = material.with_texture(&texture_id);
+    }
+
+    // map_Bump is read as a tangent-space normal map, which is what most exporters write
+    if let Some(texture) = &obj_material.normal_texture {
+        let texture_path = base_dir.join(texture);
+        let texture_id = texture_path.to_string_lossy().to_string();
+        texture_manager.load_texture(&texture_id, &texture_id)?;
+        material = material.with_normal_map(&texture_id, NormalMapFormat::OpenGl);
+    }
+
+    // disp is a height map, used for parallax as deep as one pixel of a block texture
+    if let Some(texture) = obj_material.unknown_param.get("disp") {
+        let texture_path = base_dir.join(texture);
+        let texture_id = texture_path.to_string_lossy().to_string();
+        texture_manager.load_texture(&texture_id, &texture_id)?;
+        material = material.with_height_map(&texture_id, 1.0 / 16.0);
+    }
+
+    let transparency = obj_material.dissolve
+        .map(|d| 1.0 - d.clamp(0.0, 1.0))
+        .unwrap_or(0.0);
+    let refractive_index = obj_material.optical_density.unwrap_or(1.0);
+    material = material.with_refraction(transparency, refractive_index);
+
+    // Illumination models 3 and above enable ray traced reflections, as strong as Ks.
+    // Phong's Ks is no Fresnel reflectance, so everything else keeps the dielectric default.
+    if let Some(3..=9) = obj_material.illumination_model {
+        let specular = obj_material.specular.map_or(0.0, |[r, g, b]| (r + g + b) / 3.0);
+        material = material.with_metallic(specular.clamp(0.0, 1.0));
+    }
+
+    // Map the Phong exponent to a roughness value
+    if let Some(shininess) = obj_material.shininess {
+        material.roughness = (2.0 / (shininess.max(0.0) + 2.0)).sqrt();
+    }
+
+    Ok(material)
+}
@@ -0,0 +1,286 @@
+use crate::math_utils::{Vec3, Color};
+use crate::materials::{Texture, TextureQuery};
+use crate::noise;
+use nalgebra::Vector4;
+
+// Coordinates a pattern is laid out in: per face like images, or through the world like a solid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Domain {
+    Uv,
+    World,
+}
+
+impl Domain {
+    fn point(&self, query: &TextureQuery) -> Vec3 {
+        match self {
+            Domain::Uv => Vec3::new(query.coords.u, query.coords.v, 0.0),
+            Domain::World => query.coords.point,
+        }
+    }
+}
+
+fn opaque(color: Color) -> Vector4<f32> {
+    Vector4::new(color.x, color.y, color.z, 1.0)
+}
+
+fn gray(value: f32) -> Vector4<f32> {
+    opaque(Color::repeat(value))
+}
+
+// Piecewise linear color ramp; stops are (position, color) in increasing position
+fn ramp(stops: &[(f32, Color)], t: f32) -> Color {
+    let Some(&(first_position, first_color)) = stops.first() else {
+        return Color::zeros();
+    };
+    if t <= first_position {
+        return first_color;
+    }
+    for pair in stops.windows(2) {
+        let ((start, from), (end, to)) = (pair[0], pair[1]);
+        if t <= end {
+            let blend = if end > start { (t - start) / (end - start) } else { 1.0 };
+            return from.lerp(&to, blend);
+        }
+    }
+    stops[stops.len() - 1].1
+}
+
+// The same color everywhere
+pub struct Constant(pub Color);
+
+impl Texture for Constant {
+    fn sample(&self, _query: &TextureQuery) -> Vector4<f32> {
+        opaque(self.0)
+    }
+}
+
+// Alternating squares, or cubes in world space
+pub struct Checker {
+    pub even: Color,
+    pub odd: Color,
+    pub size: f32,
+    pub domain: Domain,
+}
+
+impl Texture for Checker {
+    fn sample(&self, query: &TextureQuery) -> Vector4<f32> {
+        let cell = (self.domain.point(query) / self.size).map(f32::floor);
+        let parity = (cell.x + cell.y + cell.z).rem_euclid(2.0);
+        opaque(if parity < 1.0 { self.even } else { self.odd })
+    }
+}
+
+// Colors changing along an axis, like terrain colored by height
+pub struct Gradient {
+    pub axis: Vec3,
+    pub stops: Vec<(f32, Color)>,
+    pub domain: Domain,
+}
+
+impl Texture for Gradient {
+    fn sample(&self, query: &TextureQuery) -> Vector4<f32> {
+        opaque(ramp(&self.stops, self.domain.point(query).dot(&self.axis)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    Worley,
+}
+
+// Grayscale fractal noise in [0, 1]
+pub struct Noise {
+    pub kind: NoiseKind,
+    pub frequency: f32, // Features per texture repeat or per block
+    pub octaves: u32,
+    pub domain: Domain,
+}
+
+impl Noise {
+    pub fn new(kind: NoiseKind, frequency: f32, octaves: u32, domain: Domain) -> Self {
+        Self { kind, frequency, octaves, domain }
+    }
+}
+
+impl Texture for Noise {
+    fn sample(&self, query: &TextureQuery) -> Vector4<f32> {
+        // Octaves finer than the pixel footprint would only alias
+        let octaves = if query.footprint > 0.0 {
+            let resolvable = (0.5 / (self.frequency * query.footprint)).log2().floor() as i32 + 1;
+            self.octaves.min(resolvable.max(1) as u32)
+        } else {
+            self.octaves
+        };
+
+        let point = self.domain.point(query) * self.frequency;
+        let value = match self.kind {
+            NoiseKind::Perlin => 0.5 + 0.5 * noise::fbm(&point, octaves, noise::perlin),
+            NoiseKind::Simplex => 0.5 + 0.5 * noise::fbm(&point, octaves, noise::simplex),
+            NoiseKind::Worley => noise::fbm(&point, octaves, noise::worley),
+        };
+        gray(value.clamp(0.0, 1.0))
+    }
+}
+
+// Bands along x bent by turbulence, the classic marble veins
+pub struct Marble {
+    pub frequency: f32,
+    pub turbulence: f32,
+    pub octaves: u32,
+    pub domain: Domain,
+}
+
+impl Texture for Marble {
+    fn sample(&self, query: &TextureQuery) -> Vector4<f32> {
+        let point = self.domain.point(query) * self.frequency;
+        let turbulence = noise::fbm(&point, self.octaves, |p| noise::perlin(p).abs());
+        let bands = (point.x + self.turbulence * turbulence) * std::f32::consts::PI;
+        gray(0.5 + 0.5 * bands.sin())
+    }
+}
+
+// Colors a grayscale texture, reading its red channel
+pub struct ColorRamp {
+    pub input: Box<dyn Texture>,
+    pub stops: Vec<(f32, Color)>,
+}
+
+impl ColorRamp {
+    pub fn new(input: impl Texture + 'static, stops: Vec<(f32, Color)>) -> Self {
+        Self { input: Box::new(input), stops }
+    }
+}
+
+impl Texture for ColorRamp {
+    fn sample(&self, query: &TextureQuery) -> Vector4<f32> {
+        let input = self.input.sample(query);
+        let color = ramp(&self.stops, input.x);
+        Vector4::new(color.x, color.y, color.z, input.w)
+    }
+}
+
+// Blend from `first` to `second`, by the red channel of `factor`
+pub struct Mix {
+    pub first: Box<dyn Texture>,
+    pub second: Box<dyn Texture>,
+    pub factor: Box<dyn Texture>,
+}
+
+impl Mix {
+    pub fn new(first: impl Texture + 'static, second: impl Texture + 'static, factor: impl Texture + 'static) -> Self {
+        Self { first: Box::new(first), second: Box::new(second), factor: Box::new(factor) }
+    }
+}
+
+impl Texture for Mix {
+    fn sample(&self, query: &TextureQuery) -> Vector4<f32> {
+        let factor = self.factor.sample(query).x.clamp(0.0, 1.0);
+        self.first.sample(query).lerp(&self.second.sample(query), factor)
+    }
+}
+
+// Repeats a texture `scale` times as often
+pub struct Scale {
+    pub input: Box<dyn Texture>,
+    pub scale: f32,
+}
+
+impl Scale {
+    pub fn new(input: impl Texture + 'static, scale: f32) -> Self {
+        Self { input: Box::new(input), scale }
+    }
+}
+
+impl Texture for Scale {
+    fn sample(&self, query: &TextureQuery) -> Vector4<f32> {
+        let mut scaled = *query;
+        scaled.coords.u *= self.scale;
+        scaled.coords.v *= self.scale;
+        scaled.coords.point *= self.scale;
+        scaled.footprint *= self.scale;
+        self.input.sample(&scaled)
+    }
+}
+
+// Product of two textures, channel by channel
+pub struct Multiply {
+    pub first: Box<dyn Texture>,
+    pub second: Box<dyn Texture>,
+}
+
+impl Multiply {
+    pub fn new(first: impl Texture + 'static, second: impl Texture + 'static) -> Self {
+        Self { first: Box::new(first), second: Box::new(second) }
+    }
+}
+
+impl Texture for Multiply {
+    fn sample(&self, query: &TextureQuery) -> Vector4<f32> {
+        self.first.sample(query).component_mul(&self.second.sample(query))
+    }
+}
+
+// White marble with gray veins running through the blocks
+pub fn marble() -> impl Texture {
+    let veins = Marble { frequency: 0.6, turbulence: 3.0, octaves: 5, domain: Domain::World };
+    ColorRamp::new(veins, vec![
+        (0.0, Color::new(0.32, 0.33, 0.36)),
+        (0.15, Color::new(0.78, 0.78, 0.80)),
+        (1.0, Color::new(0.94, 0.93, 0.91)),
+    ])
+}
+
+// Speckled gray stone, lighter and darker from one area of the world to the next
+pub fn stone() -> impl Texture {
+    let speckles = ColorRamp::new(Noise::new(NoiseKind::Simplex, 8.0, 3, Domain::Uv), vec![
+        (0.2, Color::repeat(0.38)),
+        (0.8, Color::repeat(0.58)),
+    ]);
+    let variation = ColorRamp::new(Noise::new(NoiseKind::Perlin, 0.15, 2, Domain::World), vec![
+        (0.3, Color::new(0.85, 0.84, 0.82)),
+        (0.7, Color::new(1.1, 1.1, 1.12)),
+    ]);
+    Multiply::new(speckles, variation)
+}
+
+// Sand near the water line, grass, bare rock and snow on top, mottled by cellular noise
+pub fn terrain() -> impl Texture {
+    let bands = Gradient {
+        axis: Vec3::new(0.0, 1.0, 0.0),
+        stops: vec![
+            (-1.0, Color::new(0.76, 0.70, 0.50)),
+            (0.0, Color::new(0.36, 0.55, 0.24)),
+            (2.5, Color::new(0.30, 0.45, 0.20)),
+            (3.5, Color::new(0.45, 0.42, 0.40)),
+            (5.0, Color::new(0.95, 0.96, 0.98)),
+        ],
+        domain: Domain::World,
+    };
+    let breakup = ColorRamp::new(Noise::new(NoiseKind::Worley, 1.5, 2, Domain::World), vec![
+        (0.0, Color::repeat(1.0)),
+        (1.0, Color::repeat(0.8)),
+    ]);
+    Multiply::new(bands, breakup)
+}
+
+// Stone with moss in patches a few blocks across, its speckles twice as fine as plain stone
+pub fn mossy_stone() -> impl Texture {
+    let patches = ColorRamp::new(Noise::new(NoiseKind::Perlin, 0.4, 3, Domain::World), vec![
+        (0.45, Color::zeros()),
+        (0.6, Color::repeat(1.0)),
+    ]);
+    Mix::new(Scale::new(stone(), 2.0), Constant(Color::new(0.28, 0.40, 0.16)), patches)
+}
+
+// Black and white marble tiles, four to a block face
+pub fn tiles() -> impl Texture {
+    let squares = Checker {
+        even: Color::repeat(1.0),
+        odd: Color::repeat(0.12),
+        size: 0.5,
+        domain: Domain::Uv,
+    };
+    Multiply::new(squares, marble())
+}
@@ -0,0 +1,68 @@
+use crate::math_utils::{Vec3, Point3f, Ray, EPSILON};
+use crate::cube::HitRecord;
+
+// Double-sided parallelogram spanned by two edges from a corner.
+// The texture is mapped upright when `edge_u` points right and `edge_v` points up.
+#[derive(Debug, Clone)]
+pub struct Quad {
+    pub corner: Point3f,
+    pub edge_u: Vec3,
+    pub edge_v: Vec3,
+    pub material_index: usize,
+}
+
+impl Quad {
+    pub fn new(corner: Point3f, edge_u: Vec3, edge_v: Vec3, material_index: usize) -> Self {
+        Self { corner, edge_u, edge_v, material_index }
+    }
+
+    pub fn bounds(&self) -> (Point3f, Point3f) {
+        let corners = [
+            self.corner,
+            self.corner + self.edge_u,
+            self.corner + self.edge_v,
+            self.corner + self.edge_u + self.edge_v,
+        ];
+        let min = corners.iter().fold(corners[0], |min, corner| min.inf(corner));
+        let max = corners.iter().fold(corners[0], |max, corner| max.sup(corner));
+        (min, max)
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let normal = self.edge_u.cross(&self.edge_v);
+        let denominator = normal.dot(&ray.direction);
+        if denominator.abs() < EPSILON {
+            return None; // Ray is parallel to the quad
+        }
+
+        let t = normal.dot(&(self.corner - ray.origin)) / denominator;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        // Planar coordinates of the hit point along both edges
+        let point = ray.at(t);
+        let relative = point - self.corner;
+        let w = normal / normal.magnitude_squared();
+        let a = w.dot(&relative.cross(&self.edge_v));
+        let b = w.dot(&self.edge_u.cross(&relative));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+
+        let mut hit_record = HitRecord {
+            point: point.coords,
+            normal: Vec3::zeros(),
+            tangent: self.edge_u.normalize(),
+            bitangent: -self.edge_v.normalize(),
+            t,
+            u: a,
+            v: 1.0 - b, // Image rows go top to bottom
+            material_index: self.material_index,
+            front_face: false,
+        };
+
+        hit_record.set_face_normal(ray, normal.normalize());
+        Some(hit_record)
+    }
+}
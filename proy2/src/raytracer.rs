@@ -1,9 +1,146 @@
 use crate::math_utils::{Vec3, Color, Ray};
 use crate::cube::{Scene, HitRecord};
-use crate::materials::{Material, TextureManager};
+use crate::materials::{Material, Texture, TextureManager, TextureHandle, TexCoords, AlphaMode};
+use crate::bsdf::SurfaceBsdf;
+use crate::spectrum::Wavelengths;
+use crate::medium::{self, FogVolume, HomogeneousMedium, MediumEvent, MediumSegment, HenyeyGreenstein, FOG_HORIZON};
 use crate::skybox::Skybox;
 use crate::camera::Camera;
-use rand::Rng;
+use crate::obj_loader;
+use crate::gltf_loader;
+use crate::vox_loader;
+use crate::structure_loader::{self, BlockMaterialTable};
+use crate::resource_pack::ResourcePack;
+use crate::sampler::{Sampler, SamplerKind};
+use crate::film::{Film, Filter, FilterKind};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Footprint of a pixel travelling along a ray, used to pick texture mip levels
+#[derive(Debug, Clone, Copy)]
+struct RayCone {
+    width: f32,
+    spread: f32, // Growth in width per unit of distance
+}
+
+impl RayCone {
+    fn width_at(&self, t: f32) -> f32 {
+        self.width + self.spread * t
+    }
+}
+
+// Scattering events a random walk inside a material may take before it is given up
+const MAX_WALK_STEPS: u32 = 256;
+
+// Adaptive sampling: every pixel takes half its share of samples, up to a cap, before its
+// variance is trusted, then unconverged pixels take batches until the frame's budget runs out
+const ADAPTIVE_FIRST_PASS_CAP: u32 = 8;
+const ADAPTIVE_BATCH: u32 = 4;
+const ADAPTIVE_MAX_FACTOR: u32 = 8; // The noisiest pixels may take this many times the average
+
+// Running sums of a pixel's samples, enough for the mean and the variance of its luminance
+#[derive(Debug, Clone, Copy, Default)]
+struct PixelStats {
+    luminance_sum: f32,
+    luminance_square_sum: f32,
+    samples: u32,
+}
+
+impl PixelStats {
+    fn add(&mut self, color: Color) {
+        let luminance = color.dot(&Color::new(0.2126, 0.7152, 0.0722));
+        self.luminance_sum += luminance;
+        self.luminance_square_sum += luminance * luminance;
+        self.samples += 1;
+    }
+    
+    // Standard error of the mean luminance, relative to the mean. The floor keeps dark
+    // pixels from sampling forever over noise nobody could see.
+    fn relative_error(&self) -> f32 {
+        if self.samples < 2 {
+            return f32::INFINITY;
+        }
+        let n = self.samples as f32;
+        let mean = self.luminance_sum / n;
+        let variance = (self.luminance_square_sum / n - mean * mean).max(0.0) * n / (n - 1.0);
+        (variance / n).sqrt() / (mean + 0.05)
+    }
+}
+
+// Materials nested deeper than this, like a fish tank in a glass house, forget the outermost one
+const MAX_NESTED_MEDIA: usize = 4;
+
+// Materials a ray is inside, innermost last, so leaving one puts the ray back in the one around it
+#[derive(Debug, Clone, Copy, Default)]
+struct MediumStack {
+    materials: [usize; MAX_NESTED_MEDIA],
+    len: usize,
+}
+
+impl MediumStack {
+    fn current(&self) -> Option<usize> {
+        self.len.checked_sub(1).map(|top| self.materials[top])
+    }
+    
+    fn enter(mut self, material: usize) -> Self {
+        if self.len == MAX_NESTED_MEDIA {
+            self.materials.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.materials[self.len] = material;
+        self.len += 1;
+        self
+    }
+    
+    // Leaving a material that was never entered, like one the camera starts in, changes nothing
+    fn leave(mut self, material: usize) -> Self {
+        if let Some(position) = self.materials[..self.len].iter().rposition(|&inside| inside == material) {
+            self.materials.copy_within(position + 1..self.len, position);
+            self.len -= 1;
+        }
+        self
+    }
+}
+
+// What a path carries from one bounce to the next
+#[derive(Debug, Clone, Copy)]
+struct PathState {
+    depth: u32,
+    cone: RayCone,
+    // Pdf of the scattering that produced the ray, or None when the sun could not have been
+    // sampled at its origin (camera rays and mirror or glass bounces)
+    bsdf_pdf: Option<f32>,
+    // Set once the path refracted through glass or water after a vertex that sampled the sun.
+    // Shadow rays cross those unbent, so the sun found past them was already counted.
+    through_dielectric: bool,
+    medium: MediumStack, // Materials the ray travels through, empty outside of everything
+    wavelengths: Option<Wavelengths>, // Set when rendering spectrally
+}
+
+impl PathState {
+    // Converts RGB input to the values traced along this path
+    fn spectrum(&self, rgb: Color) -> Color {
+        match &self.wavelengths {
+            Some(wavelengths) => wavelengths.upsample(&rgb),
+            None => rgb,
+        }
+    }
+    
+    fn medium_spectrum(&self, medium: &HomogeneousMedium) -> HomogeneousMedium {
+        HomogeneousMedium {
+            absorption: self.spectrum(medium.absorption),
+            scattering: self.spectrum(medium.scattering),
+            phase: medium.phase,
+        }
+    }
+}
+
+// How light samples and BSDF samples of the sun are weighted against each other
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MisHeuristic {
+    Balance,
+    Power, // Squares the pdfs, favoring whichever strategy is clearly better
+}
 
 pub struct Raytracer {
     pub scene: Scene,
@@ -12,6 +149,16 @@ pub struct Raytracer {
     pub skybox: Skybox,
     pub max_depth: u32,
     pub samples_per_pixel: u32,
+    pub mis_heuristic: MisHeuristic,
+    pub spectral: bool, // Trace wavelengths instead of RGB, needed for dispersion
+    pub fog_volumes: Vec<FogVolume>, // Haze in the air; materials carry their own interior media
+    pub sampler: SamplerKind,
+    pub seed: u32,      // Same seed and settings give the same image, bit for bit
+    // Relative error a pixel stops sampling at, spending the saved samples on noisier pixels.
+    // 0 gives every pixel exactly `samples_per_pixel`.
+    pub noise_threshold: f32,
+    pub filter: Filter, // Reconstruction filter samples are splatted with
+    pub threads: usize, // Threads rendering rows in parallel, 0 uses every core
 }
 
 impl Raytracer {
@@ -23,76 +170,567 @@ impl Raytracer {
             skybox: Skybox::new(),
             max_depth: 10,
             samples_per_pixel: 4,
+            mis_heuristic: MisHeuristic::Power,
+            spectral: false,
+            fog_volumes: Vec::new(),
+            sampler: SamplerKind::Sobol,
+            seed: 0,
+            noise_threshold: 0.0,
+            filter: Filter::new(FilterKind::Box),
+            threads: 0,
         }
     }
     
-    pub fn add_material(&mut self, material: Material) -> usize {
+    // Scene time in seconds, drives animated textures and the clouds
+    pub fn set_time(&mut self, time: f32) {
+        self.texture_manager.time = time;
+        self.skybox.time = time;
+    }
+    
+    // Textures referenced by the material must already be loaded
+    pub fn add_material(&mut self, mut material: Material) -> usize {
+        material.resolve_textures(&self.texture_manager);
         self.materials.push(material);
         self.materials.len() - 1
     }
     
-    pub fn load_texture(&mut self, id: &str, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn load_texture(&mut self, id: &str, path: &str) -> Result<TextureHandle, Box<dyn std::error::Error>> {
         self.texture_manager.load_texture(id, path)
     }
     
-    fn ray_color(&self, ray: &Ray, depth: u32) -> Color {
-        if depth == 0 {
+    // Procedural or otherwise generated textures, referenced by id like loaded ones
+    pub fn add_texture(&mut self, id: &str, texture: impl Texture + 'static) -> TextureHandle {
+        self.texture_manager.add_texture(id, texture)
+    }
+    
+    // Re-resolves texture names of every material, e.g. after loading textures late
+    pub fn resolve_textures(&mut self) {
+        for material in &mut self.materials {
+            material.resolve_textures(&self.texture_manager);
+        }
+    }
+    
+    pub fn load_obj(&mut self, path: &str, offset: Vec3, scale: f32) -> Result<(), Box<dyn std::error::Error>> {
+        let meshes = obj_loader::load_obj(path, offset, scale, &mut self.materials, &mut self.texture_manager)?;
+        for mesh in meshes {
+            self.scene.add_mesh(mesh);
+        }
+        self.resolve_textures();
+        Ok(())
+    }
+    
+    pub fn load_gltf(&mut self, path: &str, aspect_ratio: f32) -> Result<Vec<Camera>, Box<dyn std::error::Error>> {
+        let gltf_scene = gltf_loader::load_gltf(path, aspect_ratio, &mut self.materials, &mut self.texture_manager)?;
+        for mesh in gltf_scene.meshes {
+            self.scene.add_mesh(mesh);
+        }
+        self.resolve_textures();
+        Ok(gltf_scene.cameras)
+    }
+    
+    pub fn load_vox(&mut self, path: &str, offset: Vec3, voxel_size: f32) -> Result<(), Box<dyn std::error::Error>> {
+        let cubes = vox_loader::load_vox(path, offset, voxel_size, &mut self.materials)?;
+        for cube in cubes {
+            self.scene.add_cube(cube);
+        }
+        Ok(())
+    }
+    
+    pub fn load_structure(&mut self, path: &str, offset: Vec3, table: &BlockMaterialTable) -> Result<(), Box<dyn std::error::Error>> {
+        structure_loader::load_structure(path, offset, table, &mut self.scene)
+    }
+    
+    // Replaces block textures of existing materials with a resource pack's, including LabPBR maps.
+    // Returns how many materials the pack changed.
+    pub fn load_resource_pack(&mut self, path: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let pack = ResourcePack::open(path)?;
+        let mut changed = 0;
+        for material in &mut self.materials {
+            if pack.apply(material, &mut self.texture_manager)? {
+                changed += 1;
+            }
+        }
+        self.resolve_textures();
+        Ok(changed)
+    }
+    
+    // Closest hit that is not on a cut out texel; rays continue through transparent texels
+    pub fn hit_scene(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut t_start = t_min;
+        loop {
+            let hit = self.scene.hit(ray, t_start, t_max)?;
+            match self.materials.get(hit.material_index) {
+                Some(material) if material.is_cut_out(&self.texture_manager, &TexCoords::from_hit(&hit)) => {
+                    t_start = hit.t + 1e-4;
+                }
+                _ => return Some(hit),
+            }
+        }
+    }
+    
+    // Shadow ray towards a light infinitely far away. Blended surfaces let light through like they let
+    // rays through, and transparent ones by their transmittance, as if light crossed them unbent.
+    fn shadow_transmittance(&self, origin: Vec3, direction: Vec3, path: &PathState, sampler: &mut dyn Sampler) -> Color {
+        let mut transmittance = Color::repeat(1.0);
+        let mut ray = Ray::new(origin.into(), direction);
+        loop {
+            let Some(hit) = self.hit_scene(&ray, 0.001, f32::INFINITY) else {
+                return transmittance;
+            };
+            let coords = TexCoords::from_hit(&hit);
+            match self.materials.get(hit.material_index) {
+                Some(material) if material.alpha_mode == AlphaMode::Blend => {
+                    if sampler.next_1d() < material.alpha(&self.texture_manager, &coords) {
+                        return Color::zeros();
+                    }
+                }
+                // Light crossing a translucent block from inside was already spread by its
+                // translucent lobe, so only the back faces of the slab it starts in let it out
+                Some(material) if material.translucency > 0.0 && !hit.front_face => {}
+                Some(material) if material.has_volume() => {
+                    // Fresnel reflection and the share of light that is not refracted stay outside
+                    let f0 = ((material.refractive_index - 1.0) / (material.refractive_index + 1.0)).powi(2);
+                    let fresnel = f0 + (1.0 - f0) * (1.0 - direction.dot(&hit.normal).abs()).powi(5);
+                    transmittance *= material.transparency * (1.0 - fresnel);
+                    
+                    // Leaving the material, the light has crossed its interior since the last hit
+                    let interior = path.medium_spectrum(&material.interior());
+                    if interior.absorption == Color::zeros() && interior.scattering == Color::zeros() {
+                        let tint = path.spectrum(material.base_color(&self.texture_manager, &coords, 0.0));
+                        transmittance.component_mul_assign(&tint.map(f32::sqrt)); // Tinted once per side
+                    } else if !hit.front_face {
+                        let extinction = interior.absorption + interior.scattering;
+                        transmittance.component_mul_assign(&extinction.map(|sigma| (-sigma * hit.t).exp()));
+                    }
+                    if transmittance == Color::zeros() {
+                        return transmittance;
+                    }
+                }
+                _ => return Color::zeros(),
+            }
+            ray = Ray::new(hit.point.into(), direction);
+        }
+    }
+    
+    fn mis_weight(&self, pdf: f32, other_pdf: f32) -> f32 {
+        match self.mis_heuristic {
+            MisHeuristic::Balance => pdf / (pdf + other_pdf),
+            MisHeuristic::Power => (pdf * pdf) / (pdf * pdf + other_pdf * other_pdf),
+        }
+    }
+    
+    // Direct sunlight through the BSDF, weighted against finding the sun by scattering
+    fn sample_sun(&self, bsdf: &SurfaceBsdf, wo: &Vec3, point: Vec3, path: &PathState, sampler: &mut dyn Sampler) -> Color {
+        let (direction, light_pdf) = self.skybox.sample_sun(sampler.next_2d());
+        if light_pdf <= 0.0 {
             return Color::zeros();
         }
         
-        if let Some(hit) = self.scene.hit(ray, 0.001, f32::INFINITY) {
-            if hit.material_index < self.materials.len() {
-                let material = &self.materials[hit.material_index];
-                
-                if let Some(scatter_result) = material.scatter(
-                    ray, 
-                    hit.point, 
-                    hit.normal, 
-                    &self.texture_manager, 
-                    hit.u, 
-                    hit.v
-                ) {
-                    let scattered_color = self.ray_color(&scatter_result.scattered_ray, depth - 1);
-                    return scatter_result.attenuation.component_mul(&scattered_color);
+        let f = bsdf.eval(wo, &direction);
+        if f == Color::zeros() {
+            return Color::zeros();
+        }
+        let shadow = self.shadow_transmittance(point, direction, path, sampler);
+        if shadow == Color::zeros() {
+            return Color::zeros();
+        }
+        let weight = self.mis_weight(light_pdf, bsdf.pdf(wo, &direction));
+        let sunlight = path.spectrum(self.skybox.sun(direction))
+            .component_mul(&self.fog_transmittance(point, direction, path))
+            .component_mul(&shadow);
+        f.component_mul(&sunlight) * (weight / light_pdf)
+    }
+    
+    // Sunlight scattered by a medium towards the ray, with the same weighting against phase sampling
+    fn sample_sun_in_medium(&self, phase: &HenyeyGreenstein, direction: &Vec3, point: Vec3, path: &PathState, sampler: &mut dyn Sampler) -> Color {
+        let (sun_direction, light_pdf) = self.skybox.sample_sun(sampler.next_2d());
+        if light_pdf <= 0.0 {
+            return Color::zeros();
+        }
+        let shadow = self.shadow_transmittance(point, sun_direction, path, sampler);
+        if shadow == Color::zeros() {
+            return Color::zeros();
+        }
+        
+        let phase_value = phase.eval(direction.dot(&sun_direction));
+        let weight = self.mis_weight(light_pdf, phase_value);
+        let sunlight = path.spectrum(self.skybox.sun(sun_direction))
+            .component_mul(&self.fog_transmittance(point, sun_direction, path))
+            .component_mul(&shadow);
+        sunlight * (phase_value * weight / light_pdf)
+    }
+    
+    // Fog between a point and the sky in the given direction
+    fn fog_transmittance(&self, point: Vec3, direction: Vec3, path: &PathState) -> Color {
+        if self.fog_volumes.is_empty() {
+            return Color::repeat(1.0);
+        }
+        let ray = Ray::new(point.into(), direction);
+        medium::transmittance(&self.fog_segments(&ray, FOG_HORIZON, path))
+    }
+    
+    fn fog_segments(&self, ray: &Ray, t_max: f32, path: &PathState) -> Vec<MediumSegment> {
+        let media: Vec<_> = self.fog_volumes.iter()
+            .filter_map(|volume| {
+                let (entry, exit) = volume.interval(ray, 0.0, t_max)?;
+                Some((path.medium_spectrum(&volume.medium), entry, exit))
+            })
+            .collect();
+        medium::segments(&media, t_max)
+    }
+    
+    // Media the ray crosses before `t_max`: the inside of the material it travels through, or the fog
+    fn media_segments(&self, ray: &Ray, t_max: f32, path: &PathState) -> Vec<MediumSegment> {
+        match path.medium.current().and_then(|index| self.materials.get(index)) {
+            Some(material) => {
+                let interior = path.medium_spectrum(&material.interior());
+                medium::segments(&[(interior, 0.0, t_max)], t_max)
+            }
+            None => self.fog_segments(ray, t_max, path),
+        }
+    }
+    
+    fn ray_color(&self, ray: &Ray, path: PathState, sampler: &mut dyn Sampler) -> Color {
+        if path.depth == 0 {
+            return Color::zeros();
+        }
+        
+        let mut ray = ray.clone();
+        let mut throughput = Color::repeat(1.0);
+        
+
+        // Inside a material light takes a random walk, scattering without spending bounces
+        for _ in 0..MAX_WALK_STEPS {
+            let hit = self.hit_scene(&ray, 0.001, f32::INFINITY);
+            
+            // Rays leaving the scene cross the fog up to the horizon before reaching the sky
+            let t_max = hit.as_ref().map_or(FOG_HORIZON, |hit| hit.t);
+            let segments = self.media_segments(&ray, t_max, &path);
+            if !segments.is_empty() {
+                let (channel_u, u) = sampler.next_2d();
+                match medium::sample_distance(&segments, channel_u, u) {
+                    MediumEvent::Scatter { t, weight, phase } => {
+                        throughput = throughput.component_mul(&weight);
+                        if path.medium.current().is_none() {
+                            return throughput.component_mul(&self.medium_color(&ray, t, &phase, path, sampler));
+                        }
+                        // The sun cannot be seen from inside, so the walk only picks a new direction
+                        let (direction, _) = phase.sample(&ray.direction, sampler.next_2d());
+                        ray = Ray::new(ray.at(t), direction);
+                        continue;
+                    }
+                    MediumEvent::Pass { weight } => throughput = throughput.component_mul(&weight),
                 }
             }
+            
+            if let Some(hit) = hit {
+                return throughput.component_mul(&self.surface_color(&ray, &hit, path, sampler));
+            }
+            
+            // Background color from skybox; the sun was also sampled directly after diffuse and glossy bounces
+            let sun = self.skybox.sun(ray.direction);
+            let sun_weight = match path.bsdf_pdf {
+                _ if path.through_dielectric => 0.0,
+                Some(pdf) if sun != Color::zeros() => self.mis_weight(pdf, self.skybox.sun_pdf(ray.direction)),
+                _ => 1.0,
+            };
+            return throughput.component_mul(&path.spectrum(self.skybox.sky(ray.direction) + sun * sun_weight));
+        }
+        
+        // Walks that never find their way out are dropped
+        Color::zeros()
+    }
+    
+    // Light scattered towards the ray origin at distance `t` inside a medium: direct sunlight and the continued path
+    fn medium_color(&self, ray: &Ray, t: f32, phase: &HenyeyGreenstein, path: PathState, sampler: &mut dyn Sampler) -> Color {
+        let point = ray.at(t).coords;
+        let direct = self.sample_sun_in_medium(phase, &ray.direction, point, &path, sampler);
+        
+        let (direction, pdf) = phase.sample(&ray.direction, sampler.next_2d());
+        let next = PathState {
+            depth: path.depth - 1,
+            cone: RayCone { width: path.cone.width_at(t), spread: path.cone.spread },
+            bsdf_pdf: Some(pdf),
+            through_dielectric: false,
+            ..path
+        };
+        // Sampling the phase function exactly leaves a weight of one
+        direct + self.ray_color(&Ray::new(point.into(), direction), next, sampler)
+    }
+    
+    // Light leaving a surface towards the ray origin: emission, direct sunlight and the scattered path
+    fn surface_color(&self, ray: &Ray, hit: &HitRecord, path: PathState, sampler: &mut dyn Sampler) -> Color {
+        let Some(material) = self.materials.get(hit.material_index) else {
             return Color::zeros();
+        };
+        
+        if material.alpha_mode == AlphaMode::Blend {
+            let alpha = material.alpha(&self.texture_manager, &TexCoords::from_hit(hit));
+            if sampler.next_1d() >= alpha {
+                let continued = Ray::new(hit.point.into(), ray.direction);
+                return self.ray_color(&continued, PathState { depth: path.depth - 1, ..path }, sampler);
+            }
+        }
+        let emitted = path.spectrum(material.emitted(&self.texture_manager, &TexCoords::from_hit(hit)));
+        
+        // Grazing angles stretch the footprint; one texture repeat spans one block
+        let cone_width = path.cone.width_at(hit.t);
+        let footprint = cone_width / ray.direction.dot(&hit.normal).abs().max(0.1);
+        
+        let bsdf = material.bsdf(ray, hit, &self.texture_manager, footprint, path.wavelengths.as_ref());
+        let direct = self.sample_sun(&bsdf, &-ray.direction, hit.point, &path, sampler);
+        
+        let (uc, u) = (sampler.next_1d(), sampler.next_2d());
+        let Some(scatter_result) = bsdf.scatter(ray, hit, uc, u) else {
+            return emitted + direct;
+        };
+        let mut attenuation = scatter_result.attenuation;
+        
+        // Refracting through a front face enters the material, through a back face leaves it
+        let transmitted = scatter_result.scattered_ray.direction.dot(&hit.normal) < 0.0;
+        // Thin translucent surfaces have no inside
+        let medium = match (transmitted && material.has_volume(), hit.front_face) {
+            (true, true) => path.medium.enter(hit.material_index),
+            (true, false) => path.medium.leave(hit.material_index),
+            (false, _) => path.medium,
+        };
+        
+        // Refracted through a dispersive surface, the other wavelengths would have gone elsewhere.
+        // Reflection sends every wavelength the same way, so mirror and glass chains keep them all.
+        let mut wavelengths = path.wavelengths;
+        if let Some(wavelengths) = &mut wavelengths
+            && transmitted
+            && material.dispersion.is_some()
+            && material.transparency > 0.0
+        {
+            attenuation = attenuation.component_mul(&wavelengths.terminate_secondary());
         }
         
-        // Background color from skybox
-        self.skybox.sample(ray.direction)
+        let next = PathState {
+            depth: path.depth - 1,
+            cone: RayCone { width: cone_width, spread: path.cone.spread },
+            bsdf_pdf: if scatter_result.is_specular { None } else { Some(scatter_result.pdf) },
+            through_dielectric: scatter_result.is_specular
+                && transmitted
+                && material.has_volume()
+                && (path.bsdf_pdf.is_some() || path.through_dielectric),
+            medium,
+            wavelengths,
+        };
+        let scattered_color = self.ray_color(&scatter_result.scattered_ray, next, sampler);
+        emitted + direct + attenuation.component_mul(&scattered_color)
     }
     
-    pub fn render_pixel(&self, camera: &Camera, x: u32, y: u32, width: u32, height: u32) -> Color {
-        let mut color = Color::zeros();
-        let mut rng = rand::thread_rng();
+    // Traces samples `samples` of a pixel, handing each to `record` with its position on the film
+    fn sample_pixel(&self, camera: &Camera, (x, y): (u32, u32), (width, height): (u32, u32), samples: Range<u32>, sampler: &mut dyn Sampler, mut record: impl FnMut((f32, f32), Color)) {
+        let cone = match camera.orthographic {
+            // Parallel rays keep a pixel's footprint at any distance
+            Some(half_height) => RayCone { width: 2.0 * half_height / height as f32, spread: 0.0 },
+            None => RayCone { width: 0.0, spread: camera.fov.to_radians() / height as f32 },
+        };
         
-        for _ in 0..self.samples_per_pixel {
-            let u = (x as f32 + rng.r#gen::<f32>()) / width as f32;
-            let v = (y as f32 + rng.r#gen::<f32>()) / height as f32;
+        for sample in samples {
+            // Camera dimensions come first, so they get the best distributed samples
+            sampler.start_sample((x, y), sample);
+            let (jitter_x, jitter_y) = sampler.next_2d();
+            let position = (x as f32 + jitter_x, y as f32 + jitter_y);
+            let u = position.0 / width as f32;
+            let v = position.1 / height as f32;
+            let lens = sampler.next_2d();
+            let wavelength_u = sampler.next_1d();
             
-            let ray = camera.get_ray(u, 1.0 - v); // Flip V coordinate
-            color += self.ray_color(&ray, self.max_depth);
+            let ray = camera.get_lens_ray(u, 1.0 - v, lens); // Flip V coordinate
+            let wavelengths = self.spectral.then(|| Wavelengths::sample(wavelength_u));
+            let path = PathState {
+                depth: self.max_depth,
+                cone,
+                bsdf_pdf: None,
+                through_dielectric: false,
+                medium: MediumStack::default(),
+                wavelengths,
+            };
+            let radiance = self.ray_color(&ray, path, sampler);
+            record(position, match &wavelengths {
+                Some(wavelengths) => wavelengths.to_rgb(&radiance),
+                None => radiance,
+            });
         }
+    }
+    
+    // Renders every row on `threads` threads and returns the rows in order
+    fn render_rows<T: Send>(&self, height: u32, render_row: impl Fn(u32) -> T + Sync) -> Vec<T> {
+        let threads = match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |count| count.get()),
+            count => count,
+        };
         
-        color / self.samples_per_pixel as f32
+        // Threads take the next row to render until none are left
+        let next_row = AtomicU32::new(0);
+        let mut rows: Vec<(u32, T)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| scope.spawn(|| {
+                    let mut rows = Vec::new();
+                    loop {
+                        let y = next_row.fetch_add(1, Ordering::Relaxed);
+                        if y >= height {
+                            return rows;
+                        }
+                        rows.push((y, render_row(y)));
+                    }
+                }))
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().expect("render thread panicked")).collect()
+        });
+        
+        rows.sort_unstable_by_key(|(y, _)| *y);
+        rows.into_iter().map(|(_, row)| row).collect()
     }
     
-    pub fn render(&self, camera: &Camera, width: u32, height: u32) -> Vec<Color> {
-        let mut pixels = vec![Color::zeros(); (width * height) as usize];
+    // Renders the image along with the number of samples each pixel took
+    pub fn render_with_samples(&self, camera: &Camera, width: u32, height: u32) -> (Vec<Color>, Vec<u32>) {
+        let adaptive = self.noise_threshold > 0.0;
+        let max_samples = if adaptive { self.samples_per_pixel * ADAPTIVE_MAX_FACTOR } else { self.samples_per_pixel };
+        // Half the budget at most goes to the first pass, leaving the rest for the noisy pixels.
+        // Whole batches keep the rounds of the stratified sampler lined up with the passes.
+        let first_pass = if adaptive {
+            (self.samples_per_pixel / 2 / ADAPTIVE_BATCH * ADAPTIVE_BATCH)
+                .clamp(2, ADAPTIVE_FIRST_PASS_CAP)
+                .min(self.samples_per_pixel)
+        } else {
+            self.samples_per_pixel
+        };
+        // Stratified samples are spread over a batch at a time, as pixels stop after any of them
+        let strata = if adaptive { ADAPTIVE_BATCH } else { self.samples_per_pixel };
+        
+        // Random numbers derive from the seed, pixel and sample alone, never from the thread.
+        // Rows splat into tiles of their own, merged in row order so sums add up the same way.
+        let mut film = Film::new(width, height, self.filter);
+        let render_pass = |film: &Film, previous: Option<&[PixelStats]>, samples: &(dyn Fn(&PixelStats) -> Range<u32> + Sync)| {
+            self.render_rows(height, |y| {
+                let mut sampler = self.sampler.create(strata, self.seed);
+                let mut tile = film.row_tile(y);
+                let row: Vec<PixelStats> = (0..width)
+                    .map(|x| {
+                        let mut stats = previous.map_or_else(PixelStats::default, |previous| previous[(y * width + x) as usize]);
+                        let samples = samples(&stats);
+                        self.sample_pixel(camera, (x, y), (width, height), samples, sampler.as_mut(), |position, color| {
+                            stats.add(color);
+                            tile.add_sample((x, y), position, color);
+                        });
+                        stats
+                    })
+                    .collect();
+                
+                // Print progress
+                if previous.is_none() && y % 10 == 0 {
+                    println!("Rendering line {} of {}", y, height);
+                }
+                (row, tile)
+            })
+        };
         
-        for y in 0..height {
-            for x in 0..width {
-                let index = (y * width + x) as usize;
-                pixels[index] = self.render_pixel(camera, x, y, width, height);
+        let rows = render_pass(&film, None, &|_| 0..first_pass);
+        let mut stats = Vec::with_capacity((width * height) as usize);
+        for (row, tile) in rows {
+            stats.extend(row);
+            film.merge(&tile);
+        }
+        
+        // Each pass gives every unconverged pixel another batch, never more than the budget left
+        let budget = self.samples_per_pixel as u64 * width as u64 * height as u64;
+        let mut spent = first_pass as u64 * width as u64 * height as u64;
+        let is_active = |stats: &PixelStats| {
+            adaptive && stats.samples < max_samples && stats.relative_error() > self.noise_threshold
+        };
+        loop {
+            let active = stats.iter().filter(|stats| is_active(stats)).count() as u64;
+            let batch = (budget.saturating_sub(spent) / active.max(1)).min(ADAPTIVE_BATCH as u64) as u32;
+            if active == 0 || batch == 0 {
+                break;
             }
-            
-            // Print progress
-            if y % 10 == 0 {
-                println!("Rendering line {} of {}", y, height);
+            let rows = render_pass(&film, Some(&stats), &|stats| {
+                if is_active(stats) { stats.samples..(stats.samples + batch).min(max_samples) } else { 0..0 }
+            });
+            let mut next = Vec::with_capacity(stats.len());
+            for (row, tile) in rows {
+                next.extend(row);
+                film.merge(&tile);
             }
+            spent += next.iter().zip(&stats).map(|(after, before)| (after.samples - before.samples) as u64).sum::<u64>();
+            stats = next;
         }
         
-        pixels
+        (film.pixels(), stats.iter().map(|stats| stats.samples).collect())
+    }
+}
+
+// Colors sample counts from dark blue for the fewest, through red, to yellow for the most
+pub fn sample_heatmap(samples: &[u32]) -> Vec<Color> {
+    let most = samples.iter().copied().max().unwrap_or(0).max(1) as f32;
+    samples.iter()
+        .map(|&count| {
+            let t = count as f32 / most;
+            if t < 0.5 {
+                Color::new(0.0, 0.0, 0.2).lerp(&Color::new(0.9, 0.05, 0.0), t * 2.0)
+            } else {
+                Color::new(0.9, 0.05, 0.0).lerp(&Color::new(1.0, 0.95, 0.1), t * 2.0 - 1.0)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::Cube;
+    use crate::film::{Filter, FilterKind};
+    use nalgebra::Point3;
+    
+    // A glass block on a diffuse floor, small enough to render in a test
+    fn test_raytracer() -> Raytracer {
+        let mut raytracer = Raytracer::new();
+        raytracer.add_material(Material::new("floor").with_albedo(0.6, 0.5, 0.4));
+        raytracer.add_material(Material::new("glass").with_refraction(1.0, 1.5).with_absorption(0.2, 0.1, 0.05));
+        raytracer.scene.add_cube(Cube::new(Point3::new(-2.0, -1.0, -2.0), Point3::new(2.0, 0.0, 2.0), 0));
+        raytracer.scene.add_cube(Cube::new(Point3::new(-0.5, 0.0, -0.5), Point3::new(0.5, 1.0, 0.5), 1));
+        raytracer.samples_per_pixel = 8;
+        raytracer.max_depth = 4;
+        raytracer.seed = 7;
+        raytracer
+    }
+    
+    fn render_bits(raytracer: &Raytracer) -> Vec<u32> {
+        let camera = Camera::new(Point3::new(0.0, 0.0, 0.0), 5.0, 45.0, 4.0 / 3.0);
+        raytracer.render_with_samples(&camera, 16, 12).0
+            .iter()
+            .flat_map(|color| [color.x.to_bits(), color.y.to_bits(), color.z.to_bits()])
+            .collect()
+    }
+    
+    #[test]
+    fn same_seed_renders_same_image_on_any_thread_count() {
+        let mut raytracer = test_raytracer();
+        for (sampler, noise_threshold, filter) in [
+            (SamplerKind::Sobol, 0.0, FilterKind::Box),
+            (SamplerKind::Stratified, 0.02, FilterKind::Mitchell),
+            (SamplerKind::Independent, 0.02, FilterKind::Lanczos),
+        ] {
+            raytracer.sampler = sampler;
+            raytracer.noise_threshold = noise_threshold;
+            raytracer.filter = Filter::new(filter);
+            
+            raytracer.threads = 1;
+            let single = render_bits(&raytracer);
+            raytracer.threads = 4;
+            assert_eq!(single, render_bits(&raytracer), "{:?} sampler", sampler);
+        }
+    }
+    
+    #[test]
+    fn different_seeds_render_different_images() {
+        let mut raytracer = test_raytracer();
+        let first = render_bits(&raytracer);
+        raytracer.seed += 1;
+        assert_ne!(first, render_bits(&raytracer));
     }
 }
@@ -0,0 +1,200 @@
+use crate::materials::{Biome, Material, TextureManager, Tint};
+use flate2::read::DeflateDecoder;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+
+type PackResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+// Texture ids of our scenes that differ from the vanilla block texture names
+const TEXTURE_ALIASES: &[(&str, &str)] = &[
+    ("grass_side", "grass_block_side"),
+    ("grass_top", "grass_block_top"),
+];
+
+// Colormaps tinting our materials by biome, keyed by texture id
+const COLORMAPS: &[(&str, &str)] = &[
+    ("grass_side", "grass"),
+    ("grass_top", "grass"),
+];
+
+// A Minecraft resource pack, unpacked in a directory or as the zip file the game loads
+pub struct ResourcePack {
+    source: PackSource,
+}
+
+enum PackSource {
+    Directory(PathBuf),
+    Zip { data: Vec<u8>, entries: HashMap<String, ZipEntry> },
+}
+
+struct ZipEntry {
+    method: u16,
+    local_header: usize,
+    compressed_size: usize,
+    size: usize,
+}
+
+// LabPBR companions found next to a block texture, as texture ids
+#[derive(Debug, Clone, Default)]
+pub struct PackTextures {
+    pub base: Option<String>,
+    pub normal: Option<String>,   // `_n`: normal, ambient occlusion and height
+    pub specular: Option<String>, // `_s`: smoothness, F0 or metal, porosity and emission
+}
+
+impl ResourcePack {
+    pub fn open(path: &str) -> PackResult<Self> {
+        let path = PathBuf::from(path);
+        let source = if path.is_dir() {
+            PackSource::Directory(path)
+        } else {
+            let data = std::fs::read(&path)?;
+            let entries = read_zip_directory(&data)?;
+            PackSource::Zip { data, entries }
+        };
+        Ok(Self { source })
+    }
+
+    fn read(&self, name: &str) -> Option<Vec<u8>> {
+        match &self.source {
+            PackSource::Directory(root) => std::fs::read(root.join(name)).ok(),
+            PackSource::Zip { data, entries } => read_zip_entry(data, entries.get(name)?).ok(),
+        }
+    }
+
+    // Loads a block texture with its animation metadata and LabPBR companions.
+    // Textures are stored as `id`, `id_n` and `id_s`, replacing previously loaded ones.
+    pub fn load_block_texture(&self, texture_manager: &mut TextureManager, id: &str, name: &str) -> PackResult<PackTextures> {
+        let path = format!("assets/minecraft/textures/block/{}", name);
+        let base_mcmeta = self.read(&format!("{}.png.mcmeta", path))
+            .and_then(|bytes| String::from_utf8(bytes).ok());
+
+        let mut textures = PackTextures::default();
+        for (suffix, slot) in [("", &mut textures.base), ("_n", &mut textures.normal), ("_s", &mut textures.specular)] {
+            let Some(bytes) = self.read(&format!("{}{}.png", path, suffix)) else {
+                continue;
+            };
+            // Companions of animated textures are animated the same way unless they say otherwise
+            let mcmeta = self.read(&format!("{}{}.png.mcmeta", path, suffix))
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .or_else(|| base_mcmeta.clone());
+
+            let texture_id = format!("{}{}", id, suffix);
+            if suffix.is_empty() {
+                texture_manager.load_texture_from_memory(&texture_id, &bytes, mcmeta.as_deref())?;
+            } else {
+                texture_manager.load_data_texture_from_memory(&texture_id, &bytes, mcmeta.as_deref())?;
+            }
+            *slot = Some(texture_id);
+        }
+        Ok(textures)
+    }
+
+    // Swaps the material's base texture for the pack's and adds its LabPBR textures.
+    // Returns false when the pack has nothing for the material.
+    pub fn apply(&self, material: &mut Material, texture_manager: &mut TextureManager) -> PackResult<bool> {
+        let Some(id) = material.texture_id.clone() else {
+            return Ok(false);
+        };
+        let name = TEXTURE_ALIASES.iter()
+            .find(|(alias, _)| *alias == id)
+            .map_or(id.as_str(), |(_, name)| name);
+
+        let textures = self.load_block_texture(texture_manager, &id, name)?;
+        if textures.base.is_none() && textures.normal.is_none() && textures.specular.is_none() {
+            return Ok(false);
+        }
+
+        *material = material.clone().with_labpbr(textures.normal.as_deref(), textures.specular.as_deref());
+
+        // Grayscale parts drawn over the base texture, like the grass on the side of grass blocks
+        if let Some(bytes) = self.read(&format!("assets/minecraft/textures/block/{}_overlay.png", name)) {
+            let overlay_id = format!("{}_overlay", id);
+            texture_manager.load_texture_from_memory(&overlay_id, &bytes, None)?;
+            *material = material.clone().with_overlay(&overlay_id);
+        }
+
+        // The colormap gives the color our presets approximate with their albedo
+        if let Some((_, colormap)) = COLORMAPS.iter().find(|(texture_id, _)| *texture_id == id)
+            && let Some(bytes) = self.read(&format!("assets/minecraft/textures/colormap/{}.png", colormap))
+        {
+            let texture_id = format!("colormap_{}", colormap);
+            texture_manager.load_texture_from_memory(&texture_id, &bytes, None)?;
+            *material = material.clone()
+                .with_albedo(1.0, 1.0, 1.0)
+                .with_tint(Tint::ColorMap { texture_id, biome: Biome::PLAINS });
+        }
+        Ok(true)
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> PackResult<u16> {
+    let bytes = data.get(offset..offset + 2).ok_or("zip file is truncated")?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> PackResult<u32> {
+    let bytes = data.get(offset..offset + 4).ok_or("zip file is truncated")?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Lists the files of a zip archive from its central directory
+fn read_zip_directory(data: &[u8]) -> PackResult<HashMap<String, ZipEntry>> {
+    const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+    const DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+
+    // The end record sits at the very end, followed by a comment of at most 64 KiB
+    let search_start = data.len().saturating_sub(22 + u16::MAX as usize);
+    let end = (search_start..data.len().saturating_sub(21))
+        .rev()
+        .find(|&offset| read_u32(data, offset).ok() == Some(END_OF_DIRECTORY))
+        .ok_or("not a zip file")?;
+
+    let entry_count = read_u16(data, end + 10)? as usize;
+    let mut offset = read_u32(data, end + 16)? as usize;
+    if offset == u32::MAX as usize {
+        return Err("zip64 archives are not supported".into());
+    }
+
+    let mut entries = HashMap::new();
+    for _ in 0..entry_count {
+        if read_u32(data, offset)? != DIRECTORY_ENTRY {
+            return Err("corrupt zip central directory".into());
+        }
+        let method = read_u16(data, offset + 10)?;
+        let compressed_size = read_u32(data, offset + 20)? as usize;
+        let size = read_u32(data, offset + 24)? as usize;
+        let name_length = read_u16(data, offset + 28)? as usize;
+        let extra_length = read_u16(data, offset + 30)? as usize;
+        let comment_length = read_u16(data, offset + 32)? as usize;
+        let local_header = read_u32(data, offset + 42)? as usize;
+
+        let name = data.get(offset + 46..offset + 46 + name_length).ok_or("zip file is truncated")?;
+        let name = String::from_utf8_lossy(name).to_string();
+        if !name.ends_with('/') {
+            entries.insert(name, ZipEntry { method, local_header, compressed_size, size });
+        }
+        offset += 46 + name_length + extra_length + comment_length;
+    }
+    Ok(entries)
+}
+
+fn read_zip_entry(data: &[u8], entry: &ZipEntry) -> PackResult<Vec<u8>> {
+    // Local headers repeat the name and may carry a different extra field
+    let name_length = read_u16(data, entry.local_header + 26)? as usize;
+    let extra_length = read_u16(data, entry.local_header + 28)? as usize;
+    let start = entry.local_header + 30 + name_length + extra_length;
+    let compressed = data.get(start..start + entry.compressed_size).ok_or("zip file is truncated")?;
+
+    match entry.method {
+        0 => Ok(compressed.to_vec()),
+        8 => {
+            // The declared size is untrusted; it only bounds the output, never preallocates it
+            let mut bytes = Vec::new();
+            DeflateDecoder::new(compressed).take(entry.size as u64).read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+        method => Err(format!("unsupported zip compression method {}", method).into()),
+    }
+}
@@ -0,0 +1,322 @@
+use std::sync::OnceLock;
+
+// Side of the tiled blue noise mask, in pixels
+const BLUE_NOISE_SIZE: usize = 64;
+
+// Source of the random numbers a path consumes. Each call takes the next dimension of the
+// current sample; low discrepancy samplers spread every dimension evenly over the pixel's samples.
+pub trait Sampler {
+    // Begins sample `index` of a pixel, counting dimensions from zero again
+    fn start_sample(&mut self, pixel: (u32, u32), index: u32);
+    fn next_1d(&mut self) -> f32;
+    fn next_2d(&mut self) -> (f32, f32);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplerKind {
+    Independent, // Uniform random numbers, no structure at all
+    Stratified,  // One jittered sample per stratum, strata shuffled per dimension
+    Sobol,       // Owen-scrambled Sobol points, shuffled per pixel and dimension
+    BlueNoise,   // Sobol points shared by all pixels, offset by a blue noise mask
+}
+
+impl SamplerKind {
+    pub fn create(&self, samples_per_pixel: u32, seed: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler { seed, state: 0 }),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed, false)),
+            SamplerKind::BlueNoise => Box::new(SobolSampler::new(seed, true)),
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            SamplerKind::Independent => SamplerKind::Stratified,
+            SamplerKind::Stratified => SamplerKind::Sobol,
+            SamplerKind::Sobol => SamplerKind::BlueNoise,
+            SamplerKind::BlueNoise => SamplerKind::Independent,
+        }
+    }
+}
+
+// Murmur3 finalizer over the running seed and the next value
+fn hash(seed: u32, value: u32) -> u32 {
+    let mut h = seed ^ value.wrapping_mul(0x9e37_79b9);
+    h = (h ^ (h >> 16)).wrapping_mul(0x85eb_ca6b);
+    h = (h ^ (h >> 13)).wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+fn to_unit_float(bits: u32) -> f32 {
+    // The top 24 bits fit the mantissa exactly, keeping the result below one
+    (bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+}
+
+pub struct IndependentSampler {
+    seed: u32,
+    state: u32,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.state = hash(hash(hash(self.seed, pixel.0), pixel.1), index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        // PCG step followed by its output permutation
+        self.state = self.state.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+        let word = ((self.state >> ((self.state >> 28) + 4)) ^ self.state).wrapping_mul(277_803_737);
+        to_unit_float((word >> 22) ^ word)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+// Kensler's hashed permutation of [0, length), a different one for every `seed`
+fn permute(mut index: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length.saturating_sub(1);
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170_893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929_eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935_fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dc_b303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e50_1cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860_a3df);
+        index &= mask;
+        index ^= index >> 5;
+        if index < length {
+            break;
+        }
+    }
+    (index.wrapping_add(seed)) % length
+}
+
+pub struct StratifiedSampler {
+    columns: u32, // 2D strata form a grid of columns by rows covering at least the sample count
+    rows: u32,
+    samples_per_pixel: u32,
+    seed: u32,
+    pixel_seed: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u32) -> Self {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        let columns = ((samples_per_pixel as f32).sqrt().round() as u32).max(1);
+        let rows = samples_per_pixel.div_ceil(columns);
+        Self { columns, rows, samples_per_pixel, seed, pixel_seed: 0, index: 0, dimension: 0 }
+    }
+
+    // Stratum of the current sample in this dimension, and a seed for jittering inside it.
+    // Samples past the count start a new round with another shuffle.
+    fn stratum(&mut self, strata: u32) -> (u32, u32) {
+        let dimension_seed = hash(hash(self.pixel_seed, self.dimension), self.index / strata);
+        self.dimension += 1;
+        (permute(self.index % strata, strata, dimension_seed), dimension_seed)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.pixel_seed = hash(hash(self.seed, pixel.0), pixel.1);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let (stratum, seed) = self.stratum(self.samples_per_pixel);
+        let jitter = to_unit_float(hash(seed, !stratum));
+        (stratum as f32 + jitter) / self.samples_per_pixel as f32
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let (stratum, seed) = self.stratum(self.columns * self.rows);
+        let (column, row) = (stratum % self.columns, stratum / self.columns);
+        let jitter = (to_unit_float(hash(seed, !stratum)), to_unit_float(hash(seed ^ 0x5bd1_e995, !stratum)));
+        (
+            (column as f32 + jitter.0) / self.columns as f32,
+            (row as f32 + jitter.1) / self.rows as f32,
+        )
+    }
+}
+
+// First two Sobol dimensions as 32-bit fractions: van der Corput, and the second generator
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut second = 0;
+    let mut direction = 1u32 << 31;
+    let mut bits = index;
+    while bits != 0 {
+        if bits & 1 != 0 {
+            second ^= direction;
+        }
+        bits >>= 1;
+        direction ^= direction >> 1;
+    }
+    (index.reverse_bits(), second)
+}
+
+// Owen scrambling through a hash that only mixes bits into less significant ones (Burley 2020)
+fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    let mut x = value.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+// Owen-scrambled Sobol points, padded two dimensions at a time. Each pair gets its own
+// scramble and order of points so dimensions stay uncorrelated. With `blue_noise` all pixels
+// share the points and are offset by a blue noise mask instead, so neighbors err differently.
+pub struct SobolSampler {
+    seed: u32,
+    blue_noise: bool,
+    pixel: (u32, u32),
+    pixel_seed: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u32, blue_noise: bool) -> Self {
+        Self { seed, blue_noise, pixel: (0, 0), pixel_seed: seed, index: 0, dimension: 0 }
+    }
+
+    fn next_point(&mut self) -> (f32, f32) {
+        let dimension_seed = hash(self.pixel_seed, self.dimension);
+        let index = nested_uniform_scramble(self.index, hash(dimension_seed, 0));
+        let (x, y) = sobol_2d(index);
+        let point = (
+            to_unit_float(nested_uniform_scramble(x, hash(dimension_seed, 1))),
+            to_unit_float(nested_uniform_scramble(y, hash(dimension_seed, 2))),
+        );
+        self.dimension += 1;
+
+        if !self.blue_noise {
+            return point;
+        }
+        // Cranley-Patterson rotation by the mask, shifted by a different amount per dimension
+        let shift = |salt: u32| {
+            let offset = hash(dimension_seed, salt) as usize;
+            let x = (self.pixel.0 as usize + offset) % BLUE_NOISE_SIZE;
+            let y = (self.pixel.1 as usize + (offset >> 16)) % BLUE_NOISE_SIZE;
+            blue_noise_mask()[y * BLUE_NOISE_SIZE + x]
+        };
+        ((point.0 + shift(3)).fract(), (point.1 + shift(4)).fract())
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.pixel = pixel;
+        self.pixel_seed = if self.blue_noise { self.seed } else { hash(hash(self.seed, pixel.0), pixel.1) };
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.next_point().0
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        self.next_point()
+    }
+}
+
+// Threshold mask made by void and cluster (Ulichney 1993): every value from 0 to 1 appears once,
+// and pixels below any threshold are spread as evenly as possible
+fn blue_noise_mask() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        const RADIUS: i32 = 6;
+        const SIGMA: f32 = 1.5;
+        let size = BLUE_NOISE_SIZE as i32;
+        let count = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+        let kernel: Vec<f32> = (-RADIUS..=RADIUS)
+            .flat_map(|dy| (-RADIUS..=RADIUS).map(move |dx| (-((dx * dx + dy * dy) as f32) / (2.0 * SIGMA * SIGMA)).exp()))
+            .collect();
+
+        // Energy of a pixel is how crowded it is by the points around it, on a torus
+        let splat = |energy: &mut [f32], pixel: usize, sign: f32| {
+            let (x, y) = ((pixel % BLUE_NOISE_SIZE) as i32, (pixel / BLUE_NOISE_SIZE) as i32);
+            for dy in -RADIUS..=RADIUS {
+                for dx in -RADIUS..=RADIUS {
+                    let target = ((y + dy).rem_euclid(size) * size + (x + dx).rem_euclid(size)) as usize;
+                    let weight = kernel[((dy + RADIUS) * (2 * RADIUS + 1) + dx + RADIUS) as usize];
+                    energy[target] += sign * weight;
+                }
+            }
+        };
+        let tightest_cluster = |energy: &[f32], points: &[bool]| {
+            (0..count).filter(|&i| points[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+        };
+        let largest_void = |energy: &[f32], points: &[bool]| {
+            (0..count).filter(|&i| !points[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+        };
+
+        // Random initial points, then relaxed by moving the tightest cluster into the largest void
+        let mut points = vec![false; count];
+        let mut energy = vec![0.0; count];
+        let mut placed = 0;
+        let mut attempt = 0;
+        while placed < count / 10 {
+            let pixel = hash(0x2545_f491, attempt) as usize % count;
+            attempt += 1;
+            if !points[pixel] {
+                points[pixel] = true;
+                splat(&mut energy, pixel, 1.0);
+                placed += 1;
+            }
+        }
+        for _ in 0..count {
+            let Some(cluster) = tightest_cluster(&energy, &points) else { break };
+            points[cluster] = false;
+            splat(&mut energy, cluster, -1.0);
+            let Some(void) = largest_void(&energy, &points) else { break };
+            points[void] = true;
+            splat(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // Ranks below the initial points come from taking clusters away, the rest from filling voids
+        let mut rank = vec![0; count];
+        let (mut removal_points, mut removal_energy) = (points.clone(), energy.clone());
+        for r in (0..placed).rev() {
+            let cluster = tightest_cluster(&removal_energy, &removal_points).unwrap_or(0);
+            removal_points[cluster] = false;
+            splat(&mut removal_energy, cluster, -1.0);
+            rank[cluster] = r;
+        }
+        for r in placed..count {
+            let Some(void) = largest_void(&energy, &points) else { break };
+            points[void] = true;
+            splat(&mut energy, void, 1.0);
+            rank[void] = r;
+        }
+
+        rank.iter().map(|&r| (r as f32 + 0.5) / count as f32).collect()
+    })
+}
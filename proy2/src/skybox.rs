@@ -1,4 +1,118 @@
-use crate::math_utils::{Vec3, Color, Lerp};
+use crate::math_utils::{Vec3, Color};
+use crate::medium::HenyeyGreenstein;
+use crate::noise;
+use std::f32::consts::PI;
+
+// Longest stretch of the cloud layer marched along one direction, in blocks
+const CLOUD_MAX_MARCH: f32 = 256.0;
+const CLOUD_VIEW_STEPS: usize = 32;
+const CLOUD_SHADOW_STEPS: usize = 6;
+
+// Minecraft-style layer of blocky clouds, seen as a volume from below
+#[derive(Debug, Clone)]
+pub struct Clouds {
+    pub coverage: f32,    // Share of the sky covered, from 0 to 1
+    pub altitude: f32,    // Height of the cloud base above the scene
+    pub thickness: f32,
+    pub cell_size: f32,   // Width of one cloud block
+    pub density: f32,     // Extinction per block travelled inside a cloud
+    pub wind: (f32, f32), // Drift along x and z, in blocks per second
+    pub phase: HenyeyGreenstein,
+}
+
+impl Clouds {
+    pub fn new() -> Self {
+        Self {
+            coverage: 0.4,
+            altitude: 64.0,
+            thickness: 4.0,
+            cell_size: 12.0,
+            density: 0.6,
+            wind: (2.0, 0.5),
+            phase: HenyeyGreenstein { g: 0.5 },
+        }
+    }
+    
+    fn is_cloud(&self, point: &Vec3, time: f32) -> bool {
+        let x = (point.x - self.wind.0 * time) / self.cell_size;
+        let z = (point.z - self.wind.1 * time) / self.cell_size;
+        let (cell_x, cell_z) = (x.floor() as i32, z.floor() as i32);
+        
+        // Cells clump together following coarse noise, with a little per cell variation
+        let clump = noise::value(&Vec3::new(cell_x as f32 / 4.0, 0.0, cell_z as f32 / 4.0));
+        let variation = noise::hash_float(cell_x, 0, cell_z);
+        0.75 * clump + 0.25 * variation < self.coverage
+    }
+    
+    // Part of the ray inside the layer
+    fn slab_interval(&self, origin: &Vec3, direction: &Vec3) -> Option<(f32, f32)> {
+        let (bottom, top) = (self.altitude, self.altitude + self.thickness);
+        let (t_start, t_end) = if direction.y.abs() < 1e-4 {
+            if origin.y < bottom || origin.y > top {
+                return None;
+            }
+            (0.0, CLOUD_MAX_MARCH)
+        } else {
+            let t_bottom = (bottom - origin.y) / direction.y;
+            let t_top = (top - origin.y) / direction.y;
+            (t_bottom.min(t_top).max(0.0), t_bottom.max(t_top))
+        };
+        let t_end = t_end.min(t_start + CLOUD_MAX_MARCH);
+        (t_end > t_start).then_some((t_start, t_end))
+    }
+    
+    fn optical_depth(&self, origin: &Vec3, direction: &Vec3, steps: usize, time: f32) -> f32 {
+        let Some((t_start, t_end)) = self.slab_interval(origin, direction) else {
+            return 0.0;
+        };
+        let step = (t_end - t_start) / steps as f32;
+        (0..steps)
+            .filter(|&i| self.is_cloud(&(origin + direction * (t_start + (i as f32 + 0.5) * step)), time))
+            .count() as f32 * self.density * step
+    }
+    
+    // Share of light from far away along the direction that gets through the clouds
+    pub fn transmittance(&self, direction: &Vec3, time: f32) -> f32 {
+        (-self.optical_depth(&Vec3::zeros(), direction, CLOUD_VIEW_STEPS, time)).exp()
+    }
+    
+    // Transmittance along the direction and the light the clouds scatter towards the viewer.
+    // `sunlight` is the irradiance of the sun, `ambient` the radiance of the sky above.
+    pub fn shade(&self, direction: &Vec3, time: f32, sun_direction: &Vec3, sunlight: Color, ambient: Color) -> (f32, Color) {
+        let Some((t_start, t_end)) = self.slab_interval(&Vec3::zeros(), direction) else {
+            return (1.0, Color::zeros());
+        };
+        let step = (t_end - t_start) / CLOUD_VIEW_STEPS as f32;
+        let phase_value = self.phase.eval(direction.dot(sun_direction));
+        
+        let mut transmittance = 1.0;
+        let mut scattered = Color::zeros();
+        for i in 0..CLOUD_VIEW_STEPS {
+            let point = direction * (t_start + (i as f32 + 0.5) * step);
+            if !self.is_cloud(&point, time) {
+                continue;
+            }
+            // Self shadowing, softened since light scattered many times inside gets further.
+            // That light leaves thick clouds nearly diffuse, hence the 1/π next to the phase function.
+            let shadow = (-0.25 * self.optical_depth(&point, sun_direction, CLOUD_SHADOW_STEPS, time)).exp();
+            let step_transmittance = (-self.density * step).exp();
+            let light = sunlight * (shadow * (phase_value + 1.0 / PI)) + ambient * 0.5;
+            // Scattering integrated exactly over the step, albedo of one
+            scattered += light * (transmittance * (1.0 - step_transmittance));
+            transmittance *= step_transmittance;
+        }
+        
+        // Far away clouds fade into the haze at the horizon
+        let fade = (-t_start / (8.0 * self.altitude)).exp();
+        (1.0 - fade * (1.0 - transmittance), scattered * fade)
+    }
+}
+
+impl Default for Clouds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 pub struct Skybox {
     pub top_color: Color,
@@ -6,7 +120,10 @@ pub struct Skybox {
     pub bottom_color: Color,
     pub sun_direction: Vec3,
     pub sun_color: Color,
-    pub sun_size: f32,
+    pub sun_size: f32,     // 1 - cosine of the angular radius of the disc
+    pub sun_strength: f32, // Radiance of the disc center relative to `sun_color`
+    pub clouds: Option<Clouds>,
+    pub time: f32, // Seconds, moves the clouds with the wind
 }
 
 impl Skybox {
@@ -18,10 +135,14 @@ impl Skybox {
             sun_direction: Vec3::new(0.3, 0.6, 0.4).normalize(),
             sun_color: Color::new(1.0, 0.9, 0.7),     // Warm yellow
             sun_size: 0.02,
+            sun_strength: 20.0,
+            clouds: Some(Clouds::new()),
+            time: 0.0,
         }
     }
     
-    pub fn sample(&self, direction: Vec3) -> Color {
+    // Everything but the sun disc, which is sampled as a light on its own
+    pub fn sky(&self, direction: Vec3) -> Color {
         let dir = direction.normalize();
         
         // Calculate the vertical gradient
@@ -37,20 +158,63 @@ impl Skybox {
             self.bottom_color.lerp(&self.horizon_color, lower_t)
         };
         
-        // Add sun
+        // Add sun glow
         let sun_dot = dir.dot(&self.sun_direction);
+        let glow_size = self.sun_size * 3.0;
+        let sky_color = if sun_dot > (1.0 - glow_size) {
+            let glow_intensity = ((sun_dot - (1.0 - glow_size)) / glow_size).max(0.0).powf(0.5) * 0.3;
+            sky_color.lerp(&self.sun_color, glow_intensity)
+        } else {
+            sky_color
+        };
+        
+        match &self.clouds {
+            Some(clouds) => {
+                let (transmittance, scattered) = clouds.shade(&dir, self.time, &self.sun_direction, self.sun_irradiance(), self.top_color);
+                sky_color * transmittance + scattered
+            }
+            None => sky_color,
+        }
+    }
+    
+    // Light arriving from the whole sun disc, facing it
+    fn sun_irradiance(&self) -> Color {
+        // Integral of the limb darkening over the cone
+        self.sun_color * (self.sun_strength * 2.0 * PI * self.sun_size / 3.0)
+    }
+    
+    // Radiance of the sun disc, darkening towards its edge and dimmed by clouds passing in front
+    pub fn sun(&self, direction: Vec3) -> Color {
+        let direction = direction.normalize();
+        let sun_dot = direction.dot(&self.sun_direction);
         if sun_dot > (1.0 - self.sun_size) {
             let sun_intensity = ((sun_dot - (1.0 - self.sun_size)) / self.sun_size).powf(2.0);
-            sky_color.lerp(&self.sun_color, sun_intensity)
+            let cloud_transmittance = self.clouds.as_ref().map_or(1.0, |clouds| clouds.transmittance(&direction, self.time));
+            self.sun_color * (sun_intensity * self.sun_strength * cloud_transmittance)
         } else {
-            // Add sun glow
-            let glow_size = self.sun_size * 3.0;
-            if sun_dot > (1.0 - glow_size) {
-                let glow_intensity = ((sun_dot - (1.0 - glow_size)) / glow_size).powf(0.5) * 0.3;
-                sky_color.lerp(&self.sun_color, glow_intensity)
-            } else {
-                sky_color
-            }
+            Color::zeros()
+        }
+    }
+    
+    // Uniform direction inside the sun disc, with its solid angle pdf
+    pub fn sample_sun(&self, u: (f32, f32)) -> (Vec3, f32) {
+        let cos_theta = 1.0 - u.0 * self.sun_size;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+        
+        let helper = if self.sun_direction.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let tangent = helper.cross(&self.sun_direction).normalize();
+        let bitangent = self.sun_direction.cross(&tangent);
+        let direction = tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + self.sun_direction * cos_theta;
+        
+        (direction, self.sun_pdf(direction))
+    }
+    
+    pub fn sun_pdf(&self, direction: Vec3) -> f32 {
+        if direction.normalize().dot(&self.sun_direction) >= 1.0 - self.sun_size {
+            1.0 / (2.0 * PI * self.sun_size)
+        } else {
+            0.0
         }
     }
 }
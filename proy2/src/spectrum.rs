@@ -0,0 +1,177 @@
+use crate::math_utils::{Color, Vec3};
+use nalgebra::Matrix3;
+use std::sync::OnceLock;
+
+// Visible range covered by the RGB upsampling tables, in nanometers
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 720.0;
+
+// Wavelengths traced together. A Color holds one radiance value per wavelength while
+// rendering spectrally, so all shading code stays the same.
+const WAVELENGTH_COUNT: usize = 3;
+
+// Smits' RGB to reflectance basis, ten bins evenly spread over the visible range
+const SMITS_WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// Refractive index as a function of wavelength
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dispersion {
+    // n = a + b / λ², λ in micrometers
+    Cauchy { a: f32, b: f32 },
+    // n² = 1 + Σ b λ² / (λ² - c), λ in micrometers
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    pub const DIAMOND: Dispersion = Dispersion::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.1750 * 0.1750, 0.1060 * 0.1060, 0.0],
+    };
+    pub const BK7_GLASS: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612, 0.231_792_34, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+    pub const WATER: Dispersion = Dispersion::Cauchy { a: 1.3242, b: 0.00305 };
+
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let micrometers = wavelength / 1000.0;
+        let l2 = micrometers * micrometers;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f32 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+// Hero wavelength sampling: one random wavelength plus others evenly spaced from it
+#[derive(Debug, Clone, Copy)]
+pub struct Wavelengths {
+    pub lambda: [f32; WAVELENGTH_COUNT],
+    pub hero_only: bool, // Set once a dispersive interface split the wavelengths apart
+}
+
+impl Wavelengths {
+    pub fn sample(u: f32) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = u * range;
+        let lambda = std::array::from_fn(|i| {
+            LAMBDA_MIN + (hero + i as f32 * range / WAVELENGTH_COUNT as f32) % range
+        });
+        Self { lambda, hero_only: false }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    // Only the hero follows the direction refraction picked for it. Its weight grows to make up
+    // for the dropped wavelengths; returns the factor to apply to the path throughput.
+    pub fn terminate_secondary(&mut self) -> Color {
+        if self.hero_only {
+            return Color::repeat(1.0);
+        }
+        self.hero_only = true;
+        Color::new(WAVELENGTH_COUNT as f32, 0.0, 0.0)
+    }
+
+    // Smits' method: a spectrum made of white plus one secondary and one primary color
+    pub fn upsample(&self, rgb: &Color) -> Color {
+        let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+        Color::from_fn(|i, _| {
+            let lambda = self.lambda[i];
+            let bin = |table: &[f32; 10]| smits_value(table, lambda);
+            if r <= g && r <= b {
+                let base = r * bin(&SMITS_WHITE);
+                if g <= b {
+                    base + (g - r) * bin(&SMITS_CYAN) + (b - g) * bin(&SMITS_BLUE)
+                } else {
+                    base + (b - r) * bin(&SMITS_CYAN) + (g - b) * bin(&SMITS_GREEN)
+                }
+            } else if g <= r && g <= b {
+                let base = g * bin(&SMITS_WHITE);
+                if r <= b {
+                    base + (r - g) * bin(&SMITS_MAGENTA) + (b - r) * bin(&SMITS_BLUE)
+                } else {
+                    base + (b - g) * bin(&SMITS_MAGENTA) + (r - b) * bin(&SMITS_RED)
+                }
+            } else {
+                let base = b * bin(&SMITS_WHITE);
+                if r <= g {
+                    base + (r - b) * bin(&SMITS_YELLOW) + (g - r) * bin(&SMITS_GREEN)
+                } else {
+                    base + (g - b) * bin(&SMITS_YELLOW) + (r - g) * bin(&SMITS_RED)
+                }
+            }
+        })
+    }
+
+    // Monte Carlo estimate of the linear sRGB color of the traced radiance values
+    pub fn to_rgb(self, radiance: &Color) -> Color {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut xyz = Vec3::zeros();
+        for (i, &lambda) in self.lambda.iter().enumerate() {
+            xyz += cie_xyz(lambda) * radiance[i];
+        }
+        // Uniform wavelength pdf, averaged over the wavelengths and normalized so Y of white is 1
+        xyz *= range / (WAVELENGTH_COUNT as f32 * cie_y_integral());
+        (XYZ_TO_SRGB * xyz).component_div(white_balance())
+    }
+}
+
+fn smits_value(table: &[f32; 10], lambda: f32) -> f32 {
+    // Linear interpolation between bin centers
+    let position = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.0 - 0.5;
+    let lower = position.floor().clamp(0.0, 9.0) as usize;
+    let upper = (lower + 1).min(9);
+    let t = (position - lower as f32).clamp(0.0, 1.0);
+    table[lower] * (1.0 - t) + table[upper] * t
+}
+
+const XYZ_TO_SRGB: Matrix3<f32> = Matrix3::new(
+    3.240_454_2, -1.537_138_5, -0.498_531_4,
+    -0.969_266, 1.876_010_8, 0.041_556,
+    0.055_643_4, -0.204_025_9, 1.057_225_2,
+);
+
+// Piecewise Gaussian fit of the CIE 1931 color matching functions (Wyman et al. 2013)
+fn cie_xyz(lambda: f32) -> Vec3 {
+    let g = |mu: f32, sigma_low: f32, sigma_high: f32| {
+        let sigma = if lambda < mu { sigma_low } else { sigma_high };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+fn cie_y_integral() -> f32 {
+    static INTEGRAL: OnceLock<f32> = OnceLock::new();
+    *INTEGRAL.get_or_init(|| integrate_cie().y)
+}
+
+// A constant spectrum comes out slightly pink in sRGB since its white point is D65;
+// dividing by its color keeps white surfaces white
+fn white_balance() -> &'static Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    WHITE.get_or_init(|| {
+        let xyz = integrate_cie();
+        XYZ_TO_SRGB * (xyz / xyz.y)
+    })
+}
+
+fn integrate_cie() -> Vec3 {
+    let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+    (0..steps).map(|step| cie_xyz(LAMBDA_MIN + step as f32 + 0.5)).sum()
+}
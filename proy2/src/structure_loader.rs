@@ -0,0 +1,231 @@
+use crate::math_utils::Vec3;
+use crate::cube::{Cube, Scene};
+use crate::block_models::BlockModel;
+use crate::nbt::{self, Tag};
+use nalgebra::Point3;
+use std::collections::HashMap;
+
+type StructureResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+const AIR_BLOCKS: [&str; 4] = [
+    "minecraft:air",
+    "minecraft:cave_air",
+    "minecraft:void_air",
+    "minecraft:structure_void",
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlockFaces {
+    pub top: usize,
+    pub bottom: usize,
+    pub side: usize,
+}
+
+impl BlockFaces {
+    pub fn new(top: usize, bottom: usize, side: usize) -> Self {
+        Self { top, bottom, side }
+    }
+
+    pub fn uniform(material_index: usize) -> Self {
+        Self::new(material_index, material_index, material_index)
+    }
+
+    pub fn face_materials(&self) -> [usize; 6] {
+        [self.side, self.side, self.top, self.bottom, self.side, self.side]
+    }
+}
+
+pub struct BlockMaterialTable {
+    blocks: HashMap<String, BlockFaces>,
+    pub fallback: Option<BlockFaces>,
+}
+
+impl BlockMaterialTable {
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            fallback: None,
+        }
+    }
+
+    // Keys are block ids ("minecraft:grass_block") or full block states ("minecraft:oak_log[axis=x]")
+    pub fn with_block(mut self, block: &str, faces: BlockFaces) -> Self {
+        self.insert(block, faces);
+        self
+    }
+
+    pub fn with_fallback(mut self, faces: BlockFaces) -> Self {
+        self.fallback = Some(faces);
+        self
+    }
+
+    pub fn insert(&mut self, block: &str, faces: BlockFaces) {
+        self.blocks.insert(normalize_block_name(block), faces);
+    }
+
+    pub fn lookup(&self, block_state: &str) -> Option<BlockFaces> {
+        let block_state = normalize_block_name(block_state);
+        let block_id = block_state.split('[').next().unwrap_or(&block_state);
+
+        self.blocks.get(&block_state)
+            .or_else(|| self.blocks.get(block_id))
+            .copied()
+            .or(self.fallback)
+    }
+}
+
+impl Default for BlockMaterialTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn normalize_block_name(block: &str) -> String {
+    if block.contains(':') {
+        block.to_string()
+    } else {
+        format!("minecraft:{}", block)
+    }
+}
+
+fn is_air(block_state: &str) -> bool {
+    let block_id = block_state.split('[').next().unwrap_or(block_state);
+    AIR_BLOCKS.contains(&normalize_block_name(block_id).as_str())
+}
+
+// Adds a vanilla structure block file (.nbt) or a Sponge schematic (.schem, versions 2 and 3)
+// to the scene. Slabs, stairs, fences, panes and plants take their block model's shape.
+pub fn load_structure(
+    path: &str,
+    offset: Vec3,
+    table: &BlockMaterialTable,
+    scene: &mut Scene,
+) -> StructureResult<()> {
+    let (_, root) = nbt::read_file(path)?;
+
+    let blocks = if root.get("blocks").is_some() {
+        read_vanilla_structure(&root)?
+    } else {
+        // Version 3 wraps everything in a "Schematic" compound
+        let schematic = root.get("Schematic").unwrap_or(&root);
+        read_sponge_schematic(schematic)?
+    };
+
+    for ([x, y, z], block_state) in blocks {
+        if is_air(&block_state) {
+            continue;
+        }
+        let Some(faces) = table.lookup(&block_state) else {
+            continue;
+        };
+
+        let min = Point3::new(x as f32, y as f32, z as f32) + offset;
+        match BlockModel::from_block_state(&block_state) {
+            BlockModel::Full => {
+                let max = min + Vec3::new(1.0, 1.0, 1.0);
+                scene.add_cube(Cube::with_face_materials(min, max, faces.face_materials()));
+            }
+            model => model.add_to_scene(scene, min, faces.side),
+        }
+    }
+
+    Ok(())
+}
+
+fn read_vanilla_structure(root: &Tag) -> StructureResult<Vec<([i32; 3], String)>> {
+    // Structures with several palettes pick one at random in game, the first is used here
+    let palette = root.get("palette")
+        .or_else(|| root.get("palettes").and_then(Tag::as_list).and_then(|palettes| palettes.first()))
+        .and_then(Tag::as_list)
+        .ok_or("structure has no palette")?;
+
+    let states: Vec<String> = palette.iter().map(block_state_string).collect();
+
+    let mut blocks = Vec::new();
+    for block in root.get("blocks").and_then(Tag::as_list).unwrap_or_default() {
+        let position = block.get("pos").and_then(Tag::as_list).ok_or("block without pos")?;
+        let coordinate = |axis: usize| position.get(axis).and_then(Tag::as_i64).map(|value| value as i32);
+        let (Some(x), Some(y), Some(z)) = (coordinate(0), coordinate(1), coordinate(2)) else {
+            return Err("malformed block position".into());
+        };
+
+        let state = block.get("state").and_then(Tag::as_i64).ok_or("block without state")?;
+        let state = states.get(state as usize).ok_or("block state out of palette range")?;
+        blocks.push(([x, y, z], state.clone()));
+    }
+
+    Ok(blocks)
+}
+
+fn block_state_string(entry: &Tag) -> String {
+    let name = entry.get("Name").and_then(Tag::as_str).unwrap_or("minecraft:air");
+    let Some(properties) = entry.get("Properties").and_then(Tag::as_compound) else {
+        return name.to_string();
+    };
+
+    // Same canonical form as Sponge palettes: properties sorted by key
+    let mut pairs: Vec<String> = properties.iter()
+        .filter_map(|(key, value)| value.as_str().map(|value| format!("{}={}", key, value)))
+        .collect();
+    pairs.sort();
+    format!("{}[{}]", name, pairs.join(","))
+}
+
+fn read_sponge_schematic(schematic: &Tag) -> StructureResult<Vec<([i32; 3], String)>> {
+    let dimension = |key: &str| -> StructureResult<usize> {
+        let value = schematic.get(key).and_then(Tag::as_i64).ok_or_else(|| format!("schematic has no {}", key))?;
+        // Dimensions are unsigned shorts
+        Ok((value as u16) as usize)
+    };
+    let width = dimension("Width")?;
+    let height = dimension("Height")?;
+    let length = dimension("Length")?;
+
+    let (palette, data) = match schematic.get("Blocks") {
+        Some(blocks) => (blocks.get("Palette"), blocks.get("Data")),
+        None => (schematic.get("Palette"), schematic.get("BlockData")),
+    };
+    let palette = palette.and_then(Tag::as_compound).ok_or("schematic has no palette")?;
+    let data = match data {
+        Some(Tag::ByteArray(data)) => data,
+        _ => return Err("schematic has no block data".into()),
+    };
+
+    let mut states = HashMap::new();
+    for (state, index) in palette {
+        if let Some(index) = index.as_i64() {
+            states.insert(index, state.as_str());
+        }
+    }
+
+    let mut blocks = Vec::new();
+    let mut bytes = data.iter().map(|byte| *byte as u8);
+    let mut index = 0;
+
+    // Block data is a sequence of varints indexed by (y * length + z) * width + x
+    while index < width * height * length {
+        let mut value: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes.next().ok_or("schematic block data is truncated")?;
+            value |= ((byte & 0x7f) as i64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+            if shift > 35 {
+                return Err("schematic varint is too long".into());
+            }
+        }
+
+        let x = index % width;
+        let z = (index / width) % length;
+        let y = index / (width * length);
+        if let Some(state) = states.get(&value) {
+            blocks.push(([x as i32, y as i32, z as i32], state.to_string()));
+        }
+        index += 1;
+    }
+
+    Ok(blocks)
+}
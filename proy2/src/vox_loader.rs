@@ -0,0 +1,360 @@
+use crate::math_utils::Vec3;
+use crate::materials::Material;
+use crate::cube::Cube;
+use nalgebra::{Matrix3, Point3};
+use std::collections::{HashMap, HashSet};
+
+type VoxResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+struct VoxModel {
+    size: [i32; 3],
+    voxels: Vec<([i32; 3], u8)>,
+}
+
+struct VoxMaterial {
+    kind: String,
+    properties: HashMap<String, String>,
+}
+
+impl VoxMaterial {
+    fn get(&self, key: &str) -> Option<f32> {
+        self.properties.get(key).and_then(|value| value.parse().ok())
+    }
+}
+
+enum VoxNode {
+    Transform { child: i32, translation: [i32; 3], rotation: Matrix3<i32> },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+struct ChunkReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ChunkReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn bytes(&mut self, count: usize) -> VoxResult<&'a [u8]> {
+        let end = self.position.checked_add(count).filter(|end| *end <= self.data.len())
+            .ok_or("unexpected end of .vox data")?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> VoxResult<i32> {
+        let bytes = self.bytes(4)?;
+        Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn count(&mut self) -> VoxResult<usize> {
+        usize::try_from(self.i32()?).map_err(|_| "negative count in .vox data".into())
+    }
+
+    fn string(&mut self) -> VoxResult<String> {
+        let length = self.count()?;
+        Ok(String::from_utf8_lossy(self.bytes(length)?).into_owned())
+    }
+
+    fn dict(&mut self) -> VoxResult<HashMap<String, String>> {
+        let entries = self.count()?;
+        let mut dict = HashMap::new();
+        for _ in 0..entries {
+            let key = self.string()?;
+            let value = self.string()?;
+            dict.insert(key, value);
+        }
+        Ok(dict)
+    }
+}
+
+pub fn load_vox(
+    path: &str,
+    offset: Vec3,
+    voxel_size: f32,
+    materials: &mut Vec<Material>,
+) -> VoxResult<Vec<Cube>> {
+    let data = std::fs::read(path)?;
+    let mut reader = ChunkReader::new(&data);
+
+    if reader.bytes(4)? != b"VOX " {
+        return Err(format!("{} is not a MagicaVoxel file", path).into());
+    }
+    let _version = reader.i32()?;
+
+    let mut models = Vec::new();
+    let mut palette: Option<Vec<[u8; 4]>> = None;
+    let mut vox_materials: HashMap<u8, VoxMaterial> = HashMap::new();
+    let mut nodes: HashMap<i32, VoxNode> = HashMap::new();
+    let mut pending_size = None;
+
+    // Chunks are flattened: the MAIN chunk has no content and every other chunk is its child
+    while !reader.is_empty() {
+        let id = reader.bytes(4)?;
+        let content_size = reader.count()?;
+        let _children_size = reader.count()?;
+        if id == b"MAIN" {
+            continue;
+        }
+
+        let mut chunk = ChunkReader::new(reader.bytes(content_size)?);
+        match id {
+            b"SIZE" => {
+                pending_size = Some([chunk.i32()?, chunk.i32()?, chunk.i32()?]);
+            }
+            b"XYZI" => {
+                let size = pending_size.take().ok_or("XYZI chunk without SIZE")?;
+                let count = chunk.count()?;
+                let mut voxels = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let voxel = chunk.bytes(4)?;
+                    voxels.push(([voxel[0] as i32, voxel[1] as i32, voxel[2] as i32], voxel[3]));
+                }
+                models.push(VoxModel { size, voxels });
+            }
+            b"RGBA" => {
+                let mut colors = Vec::with_capacity(256);
+                for _ in 0..256 {
+                    let color = chunk.bytes(4)?;
+                    colors.push([color[0], color[1], color[2], color[3]]);
+                }
+                palette = Some(colors);
+            }
+            b"MATL" => {
+                let material_id = chunk.i32()?;
+                let mut properties = chunk.dict()?;
+                let kind = properties.remove("_type").unwrap_or_else(|| "_diffuse".to_string());
+                if let Ok(index) = u8::try_from(material_id) {
+                    vox_materials.insert(index, VoxMaterial { kind, properties });
+                }
+            }
+            b"nTRN" => {
+                let node_id = chunk.i32()?;
+                let _attributes = chunk.dict()?;
+                let child = chunk.i32()?;
+                let _reserved = chunk.i32()?;
+                let _layer = chunk.i32()?;
+                let frame_count = chunk.count()?;
+
+                // Only the first animation frame is used
+                let mut translation = [0, 0, 0];
+                let mut rotation = Matrix3::identity();
+                for frame in 0..frame_count {
+                    let attributes = chunk.dict()?;
+                    if frame > 0 {
+                        continue;
+                    }
+                    if let Some(t) = attributes.get("_t") {
+                        let values: Vec<i32> = t.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+                        if let [x, y, z] = values[..] {
+                            translation = [x, y, z];
+                        }
+                    }
+                    if let Some(r) = attributes.get("_r").and_then(|r| r.parse::<u8>().ok()) {
+                        rotation = decode_rotation(r);
+                    }
+                }
+                nodes.insert(node_id, VoxNode::Transform { child, translation, rotation });
+            }
+            b"nGRP" => {
+                let node_id = chunk.i32()?;
+                let _attributes = chunk.dict()?;
+                let child_count = chunk.count()?;
+                let mut children = Vec::with_capacity(child_count);
+                for _ in 0..child_count {
+                    children.push(chunk.i32()?);
+                }
+                nodes.insert(node_id, VoxNode::Group { children });
+            }
+            b"nSHP" => {
+                let node_id = chunk.i32()?;
+                let _attributes = chunk.dict()?;
+                let model_count = chunk.count()?;
+                let mut shape_models = Vec::with_capacity(model_count);
+                for _ in 0..model_count {
+                    shape_models.push(chunk.i32()?);
+                    let _model_attributes = chunk.dict()?;
+                }
+                nodes.insert(node_id, VoxNode::Shape { models: shape_models });
+            }
+            _ => {} // Layers, cameras and render settings are ignored
+        }
+    }
+
+    // Resolve the scene graph into a world transform per model instance
+    let mut instances: Vec<(usize, Matrix3<i32>, [i32; 3])> = Vec::new();
+    if nodes.is_empty() {
+        instances.extend((0..models.len()).map(|index| (index, Matrix3::identity(), [0, 0, 0])));
+    } else {
+        // Every node has one parent, so meeting one twice means the graph loops back on itself
+        let mut visited = HashSet::new();
+        let mut stack = vec![(0, Matrix3::identity(), [0, 0, 0])];
+        while let Some((node_id, rotation, translation)) = stack.pop() {
+            if !visited.insert(node_id) {
+                return Err(format!("{}: scene node {} is reached more than once", path, node_id).into());
+            }
+            match nodes.get(&node_id) {
+                Some(VoxNode::Transform { child, translation: local_t, rotation: local_r }) => {
+                    let t = rotation * nalgebra::Vector3::new(local_t[0], local_t[1], local_t[2]);
+                    stack.push((
+                        *child,
+                        rotation * local_r,
+                        [translation[0] + t.x, translation[1] + t.y, translation[2] + t.z],
+                    ));
+                }
+                Some(VoxNode::Group { children }) => {
+                    stack.extend(children.iter().map(|child| (*child, rotation, translation)));
+                }
+                Some(VoxNode::Shape { models: shape_models }) => {
+                    for model in shape_models {
+                        if let Ok(index) = usize::try_from(*model)
+                            && index < models.len()
+                        {
+                            instances.push((index, rotation, translation));
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+
+    // One material per palette entry actually used
+    let mut material_indices: HashMap<u8, usize> = HashMap::new();
+    let mut voxels: HashMap<(i32, i32, i32), usize> = HashMap::new();
+
+    for (model_index, rotation, translation) in instances {
+        let model = &models[model_index];
+        let rotation = rotation.map(|value| value as f32);
+
+        for (position, color_index) in &model.voxels {
+            // Models rotate around their center, voxels are offset by half a unit
+            let local = nalgebra::Vector3::new(
+                position[0] as f32 + 0.5 - (model.size[0] / 2) as f32,
+                position[1] as f32 + 0.5 - (model.size[1] / 2) as f32,
+                position[2] as f32 + 0.5 - (model.size[2] / 2) as f32,
+            );
+            let world = rotation * local;
+            let x = (world.x + translation[0] as f32).floor() as i32;
+            let y = (world.y + translation[1] as f32).floor() as i32;
+            let z = (world.z + translation[2] as f32).floor() as i32;
+
+            let material_index = *material_indices.entry(*color_index).or_insert_with(|| {
+                let color = palette_color(palette.as_deref(), *color_index);
+                materials.push(convert_material(*color_index, color, vox_materials.get(color_index)));
+                materials.len() - 1
+            });
+
+            // MagicaVoxel is Z-up, the scene is Y-up
+            voxels.insert((x, z, -y - 1), material_index);
+        }
+    }
+
+    Ok(merge_voxels(voxels, offset, voxel_size))
+}
+
+fn decode_rotation(packed: u8) -> Matrix3<i32> {
+    // Row-major permutation matrix: two bits per row for the column index, then one sign bit per row
+    let first = (packed & 3) as usize;
+    let second = ((packed >> 2) & 3) as usize;
+    let third = 3 - first - second;
+    let mut rotation = Matrix3::zeros();
+    for (row, column) in [first, second, third].into_iter().enumerate() {
+        if column > 2 {
+            return Matrix3::identity();
+        }
+        rotation[(row, column)] = if packed & (1 << (4 + row)) != 0 { -1 } else { 1 };
+    }
+    rotation
+}
+
+fn palette_color(palette: Option<&[[u8; 4]]>, color_index: u8) -> [u8; 4] {
+    // Voxel color indices are 1-based into the palette
+    match palette {
+        Some(palette) => palette[(color_index as usize + 255) % 256],
+        // Files written by MagicaVoxel always include a palette
+        None => [color_index, color_index, color_index, 255],
+    }
+}
+
+fn convert_material(color_index: u8, color: [u8; 4], vox_material: Option<&VoxMaterial>) -> Material {
+    let [r, g, b, _] = color;
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let mut material = Material::new(&format!("vox_{}", color_index))
+        .with_albedo(r, g, b);
+
+    let Some(vox_material) = vox_material else {
+        return material;
+    };
+
+    if let Some(rough) = vox_material.get("_rough") {
+        material.roughness = rough;
+    }
+
+    match vox_material.kind.as_str() {
+        "_metal" => {
+            let metal = vox_material.get("_metal").unwrap_or(1.0);
+            material = material.with_metallic(metal);
+        }
+        "_glass" => {
+            let transparency = vox_material.get("_trans").or(vox_material.get("_alpha")).unwrap_or(0.5);
+            // MagicaVoxel stores the index of refraction minus one
+            let ior = 1.0 + vox_material.get("_ior").unwrap_or(0.5);
+            material = material.with_refraction(transparency, ior);
+        }
+        "_emit" => {
+            let emit = vox_material.get("_emit").unwrap_or(1.0);
+            let flux = vox_material.get("_flux").unwrap_or(0.0);
+            let strength = emit * (1.0 + flux);
+            material = material.with_emission(r * strength, g * strength, b * strength);
+        }
+        _ => {}
+    }
+
+    material
+}
+
+fn merge_voxels(voxels: HashMap<(i32, i32, i32), usize>, offset: Vec3, voxel_size: f32) -> Vec<Cube> {
+    // Merge runs of equal voxels along X so flat-colored rows become a single cube
+    let mut sorted: Vec<((i32, i32, i32), usize)> = voxels.into_iter().collect();
+    sorted.sort_by_key(|((x, y, z), _)| (*y, *z, *x));
+
+    let mut cubes = Vec::new();
+    let mut run: Option<((i32, i32, i32), i32, usize)> = None;
+
+    let mut push_run = |(x, y, z): (i32, i32, i32), length: i32, material_index: usize| {
+        let min = Point3::new(x as f32, y as f32, z as f32) * voxel_size + offset;
+        let max = Point3::new((x + length) as f32, (y + 1) as f32, (z + 1) as f32) * voxel_size + offset;
+        cubes.push(Cube::new(min, max, material_index));
+    };
+
+    for ((x, y, z), material_index) in sorted {
+        match run {
+            Some((start, length, run_material))
+                if start.1 == y && start.2 == z && start.0 + length == x && run_material == material_index =>
+            {
+                run = Some((start, length + 1, run_material));
+            }
+            _ => {
+                if let Some((start, length, run_material)) = run {
+                    push_run(start, length, run_material);
+                }
+                run = Some(((x, y, z), 1, material_index));
+            }
+        }
+    }
+
+    if let Some((start, length, run_material)) = run {
+        push_run(start, length, run_material);
+    }
+
+    cubes
+}